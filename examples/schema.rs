@@ -3,11 +3,20 @@ use std::env::current_dir;
 use std::fs::create_dir_all;
 
 use figaro_contract::{
+  ApplicationsResponse,
+  CouponMessageResponse,
+  DeadlineResponse,
   ExecuteMsg,
   FundsInfoResponse,
   InstantiateMsg,
+  InsurancePoolResponse,
   LocationsResponse,
+  OpenOrdersResponse,
+  OrderResponse,
+  OrdersResponse,
   QueryMsg,
+  ReceiveMsg,
+  ReviewsResponse,
   TokenInfoResponse,
 };
 
@@ -19,8 +28,17 @@ fn main() {
 
   export_schema(&schema_for!(InstantiateMsg), &out_dir);
   export_schema(&schema_for!(ExecuteMsg), &out_dir);
+  export_schema(&schema_for!(ReceiveMsg), &out_dir);
   export_schema(&schema_for!(QueryMsg), &out_dir);
   export_schema(&schema_for!(FundsInfoResponse), &out_dir);
   export_schema(&schema_for!(LocationsResponse), &out_dir);
   export_schema(&schema_for!(TokenInfoResponse), &out_dir);
+  export_schema(&schema_for!(OrderResponse), &out_dir);
+  export_schema(&schema_for!(OrdersResponse), &out_dir);
+  export_schema(&schema_for!(ApplicationsResponse), &out_dir);
+  export_schema(&schema_for!(CouponMessageResponse), &out_dir);
+  export_schema(&schema_for!(DeadlineResponse), &out_dir);
+  export_schema(&schema_for!(ReviewsResponse), &out_dir);
+  export_schema(&schema_for!(InsurancePoolResponse), &out_dir);
+  export_schema(&schema_for!(OpenOrdersResponse), &out_dir);
 }
@@ -1,11 +1,11 @@
-use cw2::set_contract_version;
+use cw2::{get_contract_version, set_contract_version};
 #[cfg(not(feature = "library"))]
 use cosmwasm_std::entry_point;
 
 use cosmwasm_std::{
+  to_json_binary,
   MessageInfo,
   StdResult,
-  to_binary,
   StdError,
   Response,
   DepsMut,
@@ -19,10 +19,19 @@ use crate::methods::{
   REPLY_DEPOSIT_RECEIVED_BY_COURIER,
   REPLY_PAYMENT_RECEIVED_BY_SENDER,
   REPLY_PAYMENT_TO_COURIER,
+  REPLY_FEE_TO_COLLECTOR,
+  REPLY_PAYMENT_TOP_UP,
+  REPLY_TIP_TO_COURIER,
+  REPLY_INSURANCE_PAYOUT,
+  REPLY_POOL_FUNDING,
+  REPLY_STAKE,
+  REPLY_UNSTAKE,
   REPLY_COURIER_REFUND,
+  REPLY_SETTLE_BOUNTY,
   REPLY_OWNER_REFUND,
 };
 
+use crate::migrations::{parse_version, run_migrations};
 use crate::methods;
 use crate::queries;
 use crate::error::*;
@@ -35,11 +44,11 @@ const CONTRACT_NAME: &str = "crates.io:cosm-figaro";
 pub fn instantiate(
   mut deps: DepsMut,
   _env: Env,
-  info: MessageInfo,
+  _info: MessageInfo,
   msg: InstantiateMsg,
 ) -> Result<Response, ContractError> {
   set_contract_version(deps.storage, CONTRACT_NAME, CONTRACT_VERSION)?;
-  msg.setup(&mut deps, &info)?;
+  msg.setup(&mut deps)?;
 
   Ok(Response::default())
 }
@@ -51,39 +60,227 @@ pub fn execute(
   info: MessageInfo,
   msg: ExecuteMsg,
 ) -> Result<Response, ContractError> {
+  if !msg.is_payable() && !info.funds.is_empty() {
+    return Err(ContractError::UnexpectedFunds {});
+  }
+
   match msg {
-    ExecuteMsg::MakePayForShipping {} => {
-      methods::sender_make_pay_for_shipping(deps, env, info)
+    ExecuteMsg::CreateOrder(msg) => {
+      methods::sender_create_order(deps, env, info, msg)
+    }
+    ExecuteMsg::UpdateOrder(msg) => {
+      methods::sender_update_order(deps, env, info, msg)
+    }
+    ExecuteMsg::Receive(wrapper) => {
+      methods::receive_cw20(deps, env, info, wrapper)
+    }
+    ExecuteMsg::MakePayForShipping { order_id } => {
+      methods::sender_make_pay_for_shipping(deps, env, info, order_id)
+    }
+    ExecuteMsg::TopUpPayment { order_id, amount } => {
+      methods::sender_top_up_payment(deps, env, info, order_id, amount)
+    }
+    ExecuteMsg::AcceptApplication {
+      order_id,
+      price,
+      message,
+      encryption_key,
+    } => methods::courier_accept_application(
+      deps,
+      env,
+      info,
+      order_id,
+      price,
+      message,
+      encryption_key,
+    ),
+    ExecuteMsg::ApproveCourier { order_id, courier } => {
+      methods::sender_approve_courier(deps, env, info, order_id, courier)
+    }
+    ExecuteMsg::RejectApplication { order_id, courier } => {
+      methods::sender_reject_application(deps, env, info, order_id, courier)
+    }
+    ExecuteMsg::MakeDepositForShipping { order_id } => {
+      methods::courier_make_deposit_for_shipping(deps, env, info, order_id)
+    }
+    ExecuteMsg::SetEncryptionKey {
+      order_id,
+      encryption_key,
+    } => methods::courier_set_encryption_key(
+      deps,
+      env,
+      info,
+      order_id,
+      encryption_key,
+    ),
+    ExecuteMsg::SetDetails { order_id, details } => {
+      methods::sender_set_details(deps, env, info, order_id, details)
+    }
+    ExecuteMsg::Settle { order_id } => {
+      methods::anyone_settle_expired_order(deps, env, info, order_id)
     }
-    ExecuteMsg::AcceptApplication {} => {
-      methods::courier_accept_application(deps, env, info)
+    ExecuteMsg::OpenDispute { order_id, reason } => {
+      methods::universal_open_dispute(deps, env, info, order_id, reason)
     }
-    ExecuteMsg::MakeDepositForShipping {} => {
-      methods::courier_make_deposit_for_shipping(deps, env, info)
+    ExecuteMsg::ResolveDispute {
+      order_id,
+      courier_payment,
+      courier_deposit,
+      reason,
+    } => methods::arbiter_resolve_dispute(
+      deps,
+      env,
+      info,
+      order_id,
+      courier_payment,
+      courier_deposit,
+      reason,
+    ),
+    ExecuteMsg::ParcelIssued { order_id } => {
+      methods::sender_gave_parcel_to_courier(deps, env, info, order_id)
+    }
+    ExecuteMsg::PickedUp { order_id } => {
+      methods::courier_picked_up_parcel(deps, env, info, order_id)
+    }
+    ExecuteMsg::ConfirmDelivery {
+      order_id,
+      sign,
+      parcel,
+    } => methods::courier_confirm_delivery(
+      deps,
+      env,
+      info,
+      order_id,
+      sign,
+      parcel.unwrap_or_default(),
+    ),
+    ExecuteMsg::CancelDelivery { order_id } => {
+      methods::universal_cancel_delivery_and_payback(deps, env, info, order_id)
+    }
+    ExecuteMsg::Stake { amount } => {
+      methods::courier_stake(deps, env, info, amount)
     }
-    ExecuteMsg::SetDetails { location, comment } => {
-      methods::sender_set_details(deps, env, info, location, comment)
+    ExecuteMsg::Unstake { amount } => {
+      methods::courier_unstake(deps, env, info, amount)
     }
-    ExecuteMsg::ParcelIssued {} => {
-      methods::sender_gave_parcel_to_courier(deps, env, info)
+    ExecuteMsg::FundInsurancePool { amount } => {
+      methods::anyone_fund_insurance_pool(deps, env, info, amount)
     }
-    ExecuteMsg::ConfirmDelivery { sign } => {
-      methods::courier_confirm_delivery(deps, env, info, sign)
+    ExecuteMsg::Tip { order_id, amount } => {
+      methods::sender_tip_courier(deps, env, info, order_id, amount)
     }
-    ExecuteMsg::CancelDelivery {} => {
-      methods::universal_cancel_delivery_and_payback(deps, env, info)
+    ExecuteMsg::LeaveReview {
+      order_id,
+      rating,
+      review,
+    } => {
+      methods::universal_leave_review(deps, env, info, order_id, rating, review)
     }
   }
 }
 
 #[cfg_attr(not(feature = "library"), entry_point)]
-pub fn query(deps: Deps, _env: Env, msg: QueryMsg) -> StdResult<Binary> {
+pub fn query(deps: Deps, env: Env, msg: QueryMsg) -> StdResult<Binary> {
   match msg {
-    QueryMsg::TokenInfo {} => to_binary(&queries::query_get_token_info(deps)?),
-    QueryMsg::Locations {} => to_binary(&queries::query_get_locations(deps)?),
-    QueryMsg::Courier {} => to_binary(&queries::query_get_courier(deps)?),
-    QueryMsg::Status {} => to_binary(&queries::query_get_status(deps)?),
-    QueryMsg::Funds {} => to_binary(&queries::query_get_funds(deps)?),
+    QueryMsg::Order { order_id } => {
+      to_json_binary(&queries::query_get_order(deps, env, order_id)?)
+    }
+    QueryMsg::TokenInfo {} => {
+      to_json_binary(&queries::query_get_token_info(deps)?)
+    }
+    QueryMsg::Fee {} => to_json_binary(&queries::query_get_fee(deps)?),
+    QueryMsg::InsurancePool {} => {
+      to_json_binary(&queries::query_get_insurance_pool(deps)?)
+    }
+    QueryMsg::PaymentAsset {} => {
+      to_json_binary(&queries::query_get_payment_asset(deps)?)
+    }
+    QueryMsg::Locations { order_id } => {
+      to_json_binary(&queries::query_get_locations(deps, order_id)?)
+    }
+    QueryMsg::Courier { order_id } => {
+      to_json_binary(&queries::query_get_courier(deps, order_id)?)
+    }
+    QueryMsg::Status { order_id } => {
+      to_json_binary(&queries::query_get_status(deps, order_id)?)
+    }
+    QueryMsg::Funds { order_id } => {
+      to_json_binary(&queries::query_get_funds(deps, order_id)?)
+    }
+    QueryMsg::Deadline { order_id } => {
+      to_json_binary(&queries::query_get_deadline(deps, env, order_id)?)
+    }
+    QueryMsg::CouponMessage { order_id, parcel } => {
+      to_json_binary(&queries::query_get_coupon_message(
+        deps,
+        env,
+        order_id,
+        parcel.unwrap_or_default(),
+      )?)
+    }
+    QueryMsg::Dispute { order_id } => {
+      to_json_binary(&queries::query_get_dispute(deps, order_id)?)
+    }
+    QueryMsg::Stake { address } => {
+      to_json_binary(&queries::query_get_stake(deps, address)?)
+    }
+    QueryMsg::Reputation { address } => {
+      to_json_binary(&queries::query_get_reputation(deps, address)?)
+    }
+    QueryMsg::Applications {
+      order_id,
+      start_after,
+      limit,
+    } => to_json_binary(&queries::query_get_applications(
+      deps,
+      order_id,
+      start_after,
+      limit,
+    )?),
+    QueryMsg::Orders {
+      status,
+      start_after,
+      limit,
+    } => to_json_binary(&queries::query_get_orders(
+      deps,
+      status,
+      start_after,
+      limit,
+    )?),
+    QueryMsg::OpenOrders {
+      area,
+      min_payment,
+      max_deposit,
+      start_after,
+      limit,
+    } => to_json_binary(&queries::query_get_open_orders(
+      deps,
+      area,
+      min_payment,
+      max_deposit,
+      start_after,
+      limit,
+    )?),
+    QueryMsg::OrdersByOwner {
+      owner,
+      start_after,
+      limit,
+    } => to_json_binary(&queries::query_get_orders_by_owner(
+      deps,
+      owner,
+      start_after,
+      limit,
+    )?),
+    QueryMsg::Reviews {
+      address,
+      start_after,
+      limit,
+    } => to_json_binary(&queries::query_get_reviews(
+      deps,
+      address,
+      start_after,
+      limit,
+    )?),
   }
 }
 
@@ -108,15 +305,71 @@ pub fn reply(deps: DepsMut, env: Env, msg: Reply) -> StdResult<Response> {
     REPLY_OWNER_REFUND => {
       methods::handle_reply_transfer_refund(deps, env, msg, REPLY_OWNER_REFUND)
     }
+    REPLY_SETTLE_BOUNTY => {
+      methods::handle_reply_transfer_refund(deps, env, msg, REPLY_SETTLE_BOUNTY)
+    }
+    REPLY_FEE_TO_COLLECTOR => methods::handle_reply_transfer_refund(
+      deps,
+      env,
+      msg,
+      REPLY_FEE_TO_COLLECTOR,
+    ),
+    REPLY_PAYMENT_TOP_UP => {
+      methods::handle_reply_transfer_extra(deps, env, msg, REPLY_PAYMENT_TOP_UP)
+    }
+    REPLY_TIP_TO_COURIER => {
+      methods::handle_reply_transfer_extra(deps, env, msg, REPLY_TIP_TO_COURIER)
+    }
+    REPLY_INSURANCE_PAYOUT => methods::handle_reply_transfer_refund(
+      deps,
+      env,
+      msg,
+      REPLY_INSURANCE_PAYOUT,
+    ),
+    REPLY_POOL_FUNDING => {
+      methods::handle_reply_transfer_funds(deps, env, msg, REPLY_POOL_FUNDING)
+    }
+    REPLY_STAKE => {
+      methods::handle_reply_transfer_funds(deps, env, msg, REPLY_STAKE)
+    }
+    REPLY_UNSTAKE => {
+      methods::handle_reply_transfer_funds(deps, env, msg, REPLY_UNSTAKE)
+    }
     id => Err(StdError::generic_err(format!("Unknown reply id: {}", id))),
   }
 }
 
 #[cfg_attr(not(feature = "library"), entry_point)]
 pub fn migrate(
-  _deps: DepsMut,
-  _env: Env,
+  mut deps: DepsMut,
+  env: Env,
   _msg: MigrateMsg,
 ) -> Result<Response, ContractError> {
-  Ok(Response::default())
+  let stored = get_contract_version(deps.storage)?;
+
+  if stored.contract != CONTRACT_NAME {
+    return Err(ContractError::CannotMigrate(stored.contract));
+  }
+
+  let from = parse_version(&stored.version)?;
+  let to = parse_version(CONTRACT_VERSION)?;
+
+  if from > to {
+    return Err(ContractError::CannotDowngrade(
+      stored.version,
+      CONTRACT_VERSION.to_string(),
+    ));
+  }
+
+  let (applied, attributes) = run_migrations(&mut deps, &env, &from, &to)?;
+  set_contract_version(deps.storage, CONTRACT_NAME, CONTRACT_VERSION)?;
+
+  let response = Response::new()
+    .add_attribute("action", "migrate")
+    .add_attribute("from_version", stored.version)
+    .add_attribute("to_version", CONTRACT_VERSION)
+    .add_attribute("applied", applied.join(","))
+    .add_attributes(attributes);
+
+  Ok(response)
 }
@@ -1,4 +1,4 @@
-use cosmwasm_std::StdError;
+use cosmwasm_std::{StdError, Uint128, Coin};
 use thiserror::Error;
 
 use crate::models::Status;
@@ -8,6 +8,9 @@ pub enum ContractError {
   #[error("{0}")]
   Std(#[from] StdError),
 
+  #[error("Order {0} not found")]
+  OrderNotFound(u64),
+
   #[error("Unauthorized")]
   Unauthorized {},
 
@@ -20,21 +23,117 @@ pub enum ContractError {
   #[error("Method expects courier or owner")]
   OwnerOrCourierExpected {},
 
+  #[error("Method expects arbiter")]
+  ArbiterExpected {},
+
+  #[error("Arbiter is not set, disputes are not available")]
+  ArbiterNotSet {},
+
+  #[error("Ruling exceeds the order payment or deposit")]
+  InvalidRuling {},
+
+  #[error("The order has no expired stage to settle")]
+  NotExpiredYet {},
+
   #[error("Already Paid")]
   AlreadyPaid {},
 
+  #[error("Expected either cw20 token address or native denom")]
+  InvalidPaymentAsset {},
+
+  #[error("Expected funds: {0}")]
+  InvalidFunds(Coin),
+
+  #[error("Native funds are not expected with this message")]
+  UnexpectedFunds {},
+
+  #[error("Tokens are not accepted, payment is made in other asset")]
+  UnexpectedToken {},
+
+  #[error("Expected amount: {0}")]
+  InvalidAmount(Uint128),
+
+  #[error("Amount must be greater than zero")]
+  ZeroAmount {},
+
+  #[error("Fee cannot exceed 10000 basis points")]
+  InvalidFee {},
+
   #[error("Owner cannot be a courier")]
   OwnerCannotBeACourier {},
 
   #[error("The courier has not apply yet")]
   CourierNotApplyYet {},
 
-  #[error("Invalid secp256k1 public key")]
+  #[error("The courier has no application for this order")]
+  ApplicationNotFound {},
+
+  #[error("The courier reputation does not meet the order requirement")]
+  ReputationTooLow {},
+
+  #[error("The courier price cannot exceed the order payment")]
+  PriceExceedsPayment {},
+
+  #[error("Message is too long, max length is {0}")]
+  MessageTooLong(usize),
+
+  #[error("Rating must be from {0} to {1}")]
+  InvalidRating(u8, u8),
+
+  #[error("The review for this order is already left")]
+  AlreadyReviewed {},
+
+  #[error("Time for reviews of this order is over")]
+  ReviewTimeOver {},
+
+  #[error("Not enough free stake, available: {0}")]
+  InsufficientStake(Uint128),
+
+  #[error("Insurance is not enabled in the contract")]
+  InsuranceDisabled {},
+
+  #[error("Invalid parcels: {0}")]
+  InvalidParcels(String),
+
+  #[error("Parcel {0} not found")]
+  ParcelNotFound(u32),
+
+  #[error("Parcel {0} is already delivered")]
+  ParcelAlreadyDelivered(u32),
+
+  #[error("Too many relay legs, max is {0}")]
+  TooManyLegs(usize),
+
+  #[error("Invalid area: {0}")]
+  InvalidArea(String),
+
+  #[error("Invalid encryption: {0}")]
+  InvalidEncryption(String),
+
+  #[error("The courier has not registered an encryption key")]
+  EncryptionKeyNotSet {},
+
+  #[error("The courier has already registered an encryption key")]
+  EncryptionKeyAlreadySet {},
+
+  #[error("Invalid public key")]
   InvalidPublicKey,
 
-  #[error("Invalid secp256k1 signature")]
+  #[error("Invalid public key: {0}")]
+  InvalidPublicKeyFormat(String),
+
+  #[error("Invalid signature")]
   InvalidSignature,
 
+  #[error("Cannot migrate from other contract: {0}")]
+  CannotMigrate(String),
+
+  #[error("Cannot migrate from newer version {0} to {1}")]
+  CannotDowngrade(String, String),
+
+  #[error("Invalid contract version: {0}")]
+  InvalidVersion(String),
+
   #[error(
     "Invalid status, you can call this method only with expected status"
   )]
@@ -1,13 +1,26 @@
 pub mod contract;
 pub mod error;
 pub mod methods;
+pub mod migrations;
 pub mod models;
 pub mod msg;
 pub mod queries;
 pub mod state;
 pub mod utils;
 
-pub use queries::{FundsInfoResponse, LocationsResponse};
+pub use queries::{
+  ApplicationsResponse,
+  CouponMessageResponse,
+  DeadlineResponse,
+  FundsInfoResponse,
+  InsurancePoolResponse,
+  LocationsResponse,
+  OpenOrdersResponse,
+  OrderResponse,
+  OrdersResponse,
+  RoughDirection,
+  ReviewsResponse,
+};
 pub use cw20::TokenInfoResponse;
 pub use models::*;
 pub use error::*;
@@ -1,15 +1,22 @@
 use cosmwasm_std::{
+  to_json_binary,
   MessageInfo,
-  to_binary,
+  from_json,
   StdResult,
   Response,
   StdError,
+  Uint128,
+  Storage,
   DepsMut,
   Reply,
+  Addr,
   Env,
 };
 
-use crate::msg::DetailsLocationInfo;
+use cw20::Cw20ReceiveMsg;
+use cw_asset::AssetInfo;
+
+use crate::msg::{CreateOrderMsg, ReceiveMsg, UpdateOrderMsg};
 use crate::models::*;
 use crate::error::*;
 use crate::state::*;
@@ -21,36 +28,158 @@ pub const REPLY_PAYMENT_RECEIVED_BY_SENDER: u64 = 1;
 pub const REPLY_PAYMENT_TO_COURIER: u64 = 3;
 pub const REPLY_COURIER_REFUND: u64 = 5;
 pub const REPLY_OWNER_REFUND: u64 = 4;
+pub const REPLY_SETTLE_BOUNTY: u64 = 6;
+pub const REPLY_FEE_TO_COLLECTOR: u64 = 7;
+pub const REPLY_PAYMENT_TOP_UP: u64 = 8;
+pub const REPLY_TIP_TO_COURIER: u64 = 9;
+pub const REPLY_INSURANCE_PAYOUT: u64 = 10;
+pub const REPLY_POOL_FUNDING: u64 = 11;
+pub const REPLY_STAKE: u64 = 12;
+pub const REPLY_UNSTAKE: u64 = 13;
+
+// Max length of the text messages, like the courier application or dispute reason
+pub const MAX_MESSAGE_LENGTH: usize = 256;
+// Max size of the encrypted delivery details, in bytes
+pub const MAX_CIPHERTEXT_LENGTH: usize = 1024;
+// Max number of relay legs after the first one
+pub const MAX_NEXT_LEGS: usize = 8;
+// Max number of additional parcels of the batch order
+pub const MAX_PARCELS: usize = 16;
+
+// Bounds of the rating in reviews
+pub const MIN_RATING: u8 = 1;
+pub const MAX_RATING: u8 = 5;
+
+pub fn sender_create_order(
+  mut deps: DepsMut,
+  _env: Env,
+  info: MessageInfo,
+  msg: CreateOrderMsg,
+) -> Result<Response, ContractError> {
+  let order_id = msg.setup(&mut deps, &info)?;
 
-pub fn sender_make_pay_for_shipping(
+  let response = Response::new()
+    .add_attribute("action", "order_created")
+    .add_attribute("order_id", order_id.to_string())
+    .add_attribute("owner", info.sender)
+    .set_data(to_json_binary(&order_id)?);
+
+  Ok(response)
+}
+
+pub fn sender_update_order(
   deps: DepsMut,
   env: Env,
   info: MessageInfo,
+  msg: UpdateOrderMsg,
 ) -> Result<Response, ContractError> {
-  let payment_amount = PAYMENT_AMOUNT.load(deps.storage)?;
-  let status = STATUS.load(deps.storage)?;
+  let order_id = msg.order_id;
+  let mut order = load_order(deps.storage, order_id)?;
+
+  let is_paid = match order.status {
+    Status::WaitPaymentBySender => false,
+    Status::WaitForCourier => true,
+    _ => {
+      return Err(ContractError::UnexpectedStatus(
+        order.status,
+        Status::WaitForCourier,
+      ))
+    }
+  };
+
+  let owner = check_is_owner(&order, &info)?;
+
+  if let Some(location) = msg.location {
+    order.rough_from_location = check_area(location.from)?;
+    order.rough_to_location = check_area(location.to)?;
+  }
+
+  if let Some(expiration_times) = msg.expiration_times {
+    order.expiration_times = expiration_times;
+  }
 
-  status.expected(Status::WaitPaymentBySender)?;
-  check_is_owner(&deps, &info)?;
+  let old_payment = order.payment_amount;
+  let old_deposit = order.deposit_amount;
+
+  order.payment_amount = msg.payment_amount.unwrap_or(old_payment);
+  order.deposit_amount = msg.deposit_amount.unwrap_or(old_deposit);
+
+  check_parcels_payment(&order)?;
+
+  let mut response = Response::new()
+    .add_attribute("action", "owner_updated_order")
+    .add_attribute("order_id", order_id.to_string())
+    .add_attribute("payment", order.payment_amount)
+    .add_attribute("deposit", order.deposit_amount);
+
+  // the paid order is funded by the difference of the payment, native coins
+  // of the difference are checked by the transfer helper
+  let transfer_msg = if is_paid && order.payment_amount > old_payment {
+    pay_to_contract_by_sender(
+      &deps,
+      &env,
+      &info,
+      order.payment_amount - old_payment,
+      REPLY_PAYMENT_TOP_UP,
+    )?
+  } else if !info.funds.is_empty() {
+    return Err(ContractError::UnexpectedFunds {});
+  } else if is_paid && order.payment_amount < old_payment {
+    Some(pay_from_contract(
+      &deps,
+      owner,
+      old_payment - order.payment_amount,
+      REPLY_OWNER_REFUND,
+    )?)
+  } else {
+    None
+  };
 
-  let contract_balance = get_contract_balance(&deps, &env)?;
+  if let Some(transfer_msg) = transfer_msg {
+    REPLY_ORDER_ID.save(deps.storage, &order_id)?;
+    response = response.add_submessage(transfer_msg);
+  }
 
-  if contract_balance >= payment_amount {
-    STATUS.save(deps.storage, &Status::WaitForCourier)?;
-    return Ok(Response::new().set_data(to_binary(&true)?));
+  // applications were made on better terms, couriers have to apply again
+  if order.payment_amount < old_payment || order.deposit_amount > old_deposit {
+    clear_applications(deps.storage, order_id)?;
   }
 
+  orders().save(deps.storage, order_id, &order)?;
+
+  Ok(response.set_data(to_json_binary(&true)?))
+}
+
+pub fn sender_make_pay_for_shipping(
+  deps: DepsMut,
+  env: Env,
+  info: MessageInfo,
+  order_id: u64,
+) -> Result<Response, ContractError> {
+  let order = load_order(deps.storage, order_id)?;
+
+  order.status.expected(Status::WaitPaymentBySender)?;
+  check_is_owner(&order, &info)?;
+
+  // payment for all relay legs and the insurance premium are made at once
   let transfer_msg = pay_to_contract_by_sender(
     &deps,
     &env,
     &info,
-    payment_amount,
+    order.total_payment() + order.insurance_premium,
     REPLY_PAYMENT_RECEIVED_BY_SENDER,
   )?;
 
+  // native coins are already received with the message
+  let Some(transfer_msg) = transfer_msg else {
+    return Ok(payment_received(deps, order_id)?);
+  };
+
+  REPLY_ORDER_ID.save(deps.storage, &order_id)?;
+
   let response = Response::new()
     .add_submessage(transfer_msg)
-    .set_data(to_binary(&true)?);
+    .set_data(to_json_binary(&true)?);
 
   Ok(response)
 }
@@ -63,17 +192,30 @@ pub fn handle_reply_transfer_payment(
   // @TODO: check if additional event checks need to be done
   let _reply = msg.result.into_result().map_err(StdError::generic_err)?;
 
-  let payment_amount = PAYMENT_AMOUNT.load(deps.storage)?;
-  let owner = OWNER.load(deps.storage)?;
+  let order_id = REPLY_ORDER_ID.load(deps.storage)?;
+  payment_received(deps, order_id)
+}
+
+fn payment_received(deps: DepsMut, order_id: u64) -> StdResult<Response> {
+  let mut order = orders().load(deps.storage, order_id)?;
 
   // Установить статус ожидания курьера, учитывать что трансфер прошел успешно.
-  STATUS.save(deps.storage, &Status::WaitForCourier)?;
+  order.status = Status::WaitForCourier;
+  orders().save(deps.storage, order_id, &order)?;
+
+  // the premium belongs to the pool from now on
+  if !order.insurance_premium.is_zero() {
+    INSURANCE_POOL.update(deps.storage, |pool| -> StdResult<_> {
+      Ok(pool + order.insurance_premium)
+    })?;
+  }
 
   let response = Response::new()
     .add_attribute("action", "owner_made_payment")
-    .add_attribute("owner", owner)
-    .add_attribute("deposit", payment_amount)
-    .set_data(to_binary(&true)?);
+    .add_attribute("order_id", order_id.to_string())
+    .add_attribute("deposit", order.total_payment())
+    .add_attribute("owner", order.owner)
+    .set_data(to_json_binary(&true)?);
 
   Ok(response)
 }
@@ -82,318 +224,1445 @@ pub fn courier_make_deposit_for_shipping(
   deps: DepsMut,
   env: Env,
   info: MessageInfo,
+  order_id: u64,
 ) -> Result<Response, ContractError> {
-  let status = STATUS.load(deps.storage)?;
-  status.expected(Status::WaitDepositByCourier)?;
+  let order = load_order(deps.storage, order_id)?;
+  order.status.expected(Status::WaitDepositByCourier)?;
 
-  check_is_courier(&deps, &info)?;
-
-  let (payment_amount, deposit_amount) = get_payments_info(&deps)?;
-  let contract_balance = get_contract_balance(&deps, &env)?;
-
-  if contract_balance >= payment_amount + deposit_amount {
-    STATUS.save(deps.storage, &Status::WaitForCourier)?;
-    return Ok(Response::new().set_data(to_binary(&true)?));
-  }
+  check_is_courier(&order, &info)?;
 
   let transfer_msg = pay_to_contract_by_sender(
     &deps,
     &env,
     &info,
-    deposit_amount,
+    order.deposit_amount,
     REPLY_DEPOSIT_RECEIVED_BY_COURIER,
   )?;
 
+  // native coins are already received with the message
+  let Some(transfer_msg) = transfer_msg else {
+    return Ok(deposit_received(deps, env, order_id)?);
+  };
+
+  REPLY_ORDER_ID.save(deps.storage, &order_id)?;
+
   Ok(
     Response::new()
       .add_submessage(transfer_msg)
-      .set_data(to_binary(&true)?),
+      .set_data(to_json_binary(&true)?),
   )
 }
 
 pub fn handle_reply_transfer_deposit(
-  mut deps: DepsMut,
+  deps: DepsMut,
   env: Env,
   msg: Reply,
 ) -> StdResult<Response> {
   // @TODO: check if additional event checks need to be done
   let _reply = msg.result.into_result().map_err(StdError::generic_err)?;
 
-  let deposit_amount = DEPOSIT_AMOUNT.load(deps.storage)?;
+  let order_id = REPLY_ORDER_ID.load(deps.storage)?;
+  deposit_received(deps, env, order_id)
+}
+
+fn deposit_received(
+  deps: DepsMut,
+  env: Env,
+  order_id: u64,
+) -> StdResult<Response> {
+  let mut order = orders().load(deps.storage, order_id)?;
 
   let courier =
-    get_courier(&deps).or(Err(StdError::generic_err("Courier not found")))?;
+    get_courier(&order).or(Err(StdError::generic_err("Courier not found")))?;
 
   // Set the status of waiting for delivery details, consider that the transfer was successful.
-  STATUS.save(deps.storage, &Status::WaitSenderDetails)?;
+  order.status = Status::WaitSenderDetails;
 
   // set expiration for set details by sender
-  let expiration_times = EXPIRATION_TIMES.load(deps.storage)?;
-  expiration_times
-    .set_expiration_by_status(&mut deps, env)
-    .or(Err(StdError::generic_err("Cannot set expiration")))?;
+  let expiration_times = order.expiration_times;
+  expiration_times.set_expiration_by_status(&mut order, &env);
+
+  orders().save(deps.storage, order_id, &order)?;
 
   let response = Response::new()
     .add_attribute("action", "courier_made_deposit")
+    .add_attribute("order_id", order_id.to_string())
     .add_attribute("courier", courier)
-    .add_attribute("deposit", deposit_amount)
-    .set_data(to_binary(&true)?);
+    .add_attribute("deposit", order.deposit_amount)
+    .set_data(to_json_binary(&true)?);
 
   Ok(response)
 }
 
-pub fn courier_accept_application(
+pub fn receive_cw20(
+  deps: DepsMut,
+  env: Env,
+  info: MessageInfo,
+  wrapper: Cw20ReceiveMsg,
+) -> Result<Response, ContractError> {
+  match PAYMENT_ASSET.load(deps.storage)? {
+    AssetInfo::Cw20(token_address) if token_address == info.sender => {}
+    _ => return Err(ContractError::UnexpectedToken {}),
+  }
+
+  // the account who sent the tokens, checks below are the same as for the direct calls
+  let info = MessageInfo {
+    sender: deps.api.addr_validate(&wrapper.sender)?,
+    funds: vec![],
+  };
+
+  match from_json(&wrapper.msg)? {
+    ReceiveMsg::PayForShipping { order_id } => {
+      let order = load_order(deps.storage, order_id)?;
+
+      order.status.expected(Status::WaitPaymentBySender)?;
+      check_is_owner(&order, &info)?;
+
+      let amount = order.total_payment() + order.insurance_premium;
+
+      if wrapper.amount != amount {
+        return Err(ContractError::InvalidAmount(amount));
+      }
+
+      Ok(payment_received(deps, order_id)?)
+    }
+
+    ReceiveMsg::DepositForShipping { order_id } => {
+      let order = load_order(deps.storage, order_id)?;
+
+      order.status.expected(Status::WaitDepositByCourier)?;
+      check_is_courier(&order, &info)?;
+
+      if wrapper.amount != order.deposit_amount {
+        return Err(ContractError::InvalidAmount(order.deposit_amount));
+      }
+
+      Ok(deposit_received(deps, env, order_id)?)
+    }
+
+    ReceiveMsg::TopUpPayment { order_id } => {
+      let order = load_order(deps.storage, order_id)?;
+
+      order.status.expected(Status::WaitForCourier)?;
+      check_is_owner(&order, &info)?;
+
+      payment_topped_up(deps, order_id, order, wrapper.amount)
+    }
+
+    ReceiveMsg::FundInsurancePool {} => {
+      insurance_pool_funded(deps, info.sender, wrapper.amount)
+    }
+
+    ReceiveMsg::Stake {} => stake_received(deps, info.sender, wrapper.amount),
+
+    ReceiveMsg::Tip { order_id } => {
+      let order = load_order(deps.storage, order_id)?;
+
+      order.status.expected(Status::Delivered)?;
+      check_is_owner(&order, &info)?;
+
+      if wrapper.amount.is_zero() {
+        return Err(ContractError::ZeroAmount {});
+      }
+
+      // tokens are already in the contract, forward them to the courier
+      let courier = get_courier(&order)?;
+      let transfer_msg = pay_from_contract(
+        &deps,
+        courier.clone(),
+        wrapper.amount,
+        REPLY_TIP_TO_COURIER,
+      )?;
+
+      REPLY_ORDER_ID.save(deps.storage, &order_id)?;
+
+      Ok(
+        tip_sent(order_id, courier, wrapper.amount)?
+          .add_submessage(transfer_msg),
+      )
+    }
+  }
+}
+
+// The owner raises the payment to attract couriers while nobody is approved
+pub fn sender_top_up_payment(
   mut deps: DepsMut,
   env: Env,
   info: MessageInfo,
+  order_id: u64,
+  amount: Uint128,
 ) -> Result<Response, ContractError> {
-  let status = STATUS.load(deps.storage)?;
-  status.expected(Status::WaitForCourier)?;
+  let order = load_order(deps.storage, order_id)?;
 
-  if check_is_owner(&deps, &info).is_ok() {
-    return Err(ContractError::OwnerCannotBeACourier {});
+  order.status.expected(Status::WaitForCourier)?;
+  check_is_owner(&order, &info)?;
+
+  let transfer_msg = pay_to_contract_by_sender(
+    &deps,
+    &env,
+    &info,
+    amount,
+    REPLY_PAYMENT_TOP_UP,
+  )?;
+
+  // the transfer fails the whole message, so the payment is raised right away
+  let response = payment_topped_up(deps.branch(), order_id, order, amount)?;
+
+  // native coins are already received with the message
+  let Some(transfer_msg) = transfer_msg else {
+    return Ok(response);
+  };
+
+  REPLY_ORDER_ID.save(deps.storage, &order_id)?;
+
+  Ok(response.add_submessage(transfer_msg))
+}
+
+fn payment_topped_up(
+  deps: DepsMut,
+  order_id: u64,
+  mut order: Order,
+  amount: Uint128,
+) -> Result<Response, ContractError> {
+  if amount.is_zero() {
+    return Err(ContractError::ZeroAmount {});
+  }
+
+  order.payment_amount += amount;
+  orders().save(deps.storage, order_id, &order)?;
+
+  let response = Response::new()
+    .add_attribute("action", "owner_topped_up_payment")
+    .add_attribute("order_id", order_id.to_string())
+    .add_attribute("amount", amount)
+    .add_attribute("payment", order.payment_amount)
+    .set_data(to_json_binary(&true)?);
+
+  Ok(response)
+}
+
+// The owner thanks the courier of the delivered order, the tip goes
+// straight to the courier
+pub fn sender_tip_courier(
+  deps: DepsMut,
+  _env: Env,
+  info: MessageInfo,
+  order_id: u64,
+  amount: Uint128,
+) -> Result<Response, ContractError> {
+  let order = load_order(deps.storage, order_id)?;
+
+  order.status.expected(Status::Delivered)?;
+  check_is_owner(&order, &info)?;
+
+  if amount.is_zero() {
+    return Err(ContractError::ZeroAmount {});
   }
 
-  COURIER.save(deps.storage, &info.sender)?;
-  STATUS.save(deps.storage, &Status::WaitDepositByCourier)?;
+  let courier = get_courier(&order)?;
+  let transfer_msg = pay_to_account_by_sender(
+    &deps,
+    &info,
+    courier.clone(),
+    amount,
+    REPLY_TIP_TO_COURIER,
+  )?;
+
+  REPLY_ORDER_ID.save(deps.storage, &order_id)?;
 
-  let expiration_times = EXPIRATION_TIMES.load(deps.storage)?;
-  expiration_times.set_expiration_by_status(&mut deps, env)?;
+  Ok(tip_sent(order_id, courier, amount)?.add_submessage(transfer_msg))
+}
 
+fn tip_sent(
+  order_id: u64,
+  courier: Addr,
+  amount: Uint128,
+) -> StdResult<Response> {
   let response = Response::new()
-    .add_attribute("action", "courier_accepted_order")
-    .add_attribute("courier", info.sender);
+    .add_attribute("action", "owner_tipped_courier")
+    .add_attribute("order_id", order_id.to_string())
+    .add_attribute("courier", courier)
+    .add_attribute("tip", amount)
+    .set_data(to_json_binary(&true)?);
 
   Ok(response)
 }
 
-pub fn sender_set_details(
-  mut deps: DepsMut,
-  env: Env,
+// Replies of the top-up and the tip transfers, state is updated before them
+pub fn handle_reply_transfer_extra(
+  deps: DepsMut,
+  _env: Env,
+  msg: Reply,
+  reply_id: u64,
+) -> StdResult<Response> {
+  // @TODO: check if additional event checks need to be done
+  let _reply = msg.result.into_result().map_err(StdError::generic_err)?;
+
+  let order_id = REPLY_ORDER_ID.load(deps.storage)?;
+
+  let action = match reply_id {
+    REPLY_PAYMENT_TOP_UP => "top_up_completed",
+    _ => "tip_completed",
+  };
+
+  let response = Response::new()
+    .add_attribute("action", action)
+    .add_attribute("order_id", order_id.to_string())
+    .set_data(to_json_binary(&true)?);
+
+  Ok(response)
+}
+
+pub fn courier_accept_application(
+  deps: DepsMut,
+  _env: Env,
   info: MessageInfo,
-  location: DetailsLocationInfo,
-  comment: String,
+  order_id: u64,
+  price: Option<Uint128>,
+  message: Option<String>,
+  encryption_key: EncryptionKey,
 ) -> Result<Response, ContractError> {
-  let status = STATUS.load(deps.storage)?;
-  status.expected(Status::WaitSenderDetails)?;
+  let order = load_order(deps.storage, order_id)?;
+  order.status.expected(Status::WaitForCourier)?;
+
+  if check_is_owner(&order, &info).is_ok() {
+    return Err(ContractError::OwnerCannotBeACourier {});
+  }
+
+  if let Some(requirement) = &order.courier_requirement {
+    let reputation = REPUTATION
+      .may_load(deps.storage, &info.sender)?
+      .unwrap_or_default();
+
+    if !requirement.is_met(&reputation) {
+      return Err(ContractError::ReputationTooLow {});
+    }
+  }
+
+  if order.bonded {
+    check_free_stake(deps.storage, &info.sender, order.deposit_amount)?;
+  }
+
+  if price.is_some_and(|price| price > order.payment_amount) {
+    return Err(ContractError::PriceExceedsPayment {});
+  }
 
-  check_is_owner(&deps, &info)?;
+  // the price lowers the share of the first parcel of the batch order
+  if price.is_some_and(|price| price < order.parcels_payment()) {
+    return Err(ContractError::InvalidParcels(
+      "price does not cover shares of the additional parcels".into(),
+    ));
+  }
+
+  check_message_length(message.as_deref().unwrap_or_default())?;
+  check_encryption_key(&encryption_key)?;
 
-  EXACT_FROM_LOCATION.save(deps.storage, &location.from)?;
-  EXACT_TO_LOCATION.save(deps.storage, &location.to)?;
-  COMMENT.save(deps.storage, &comment)?;
-  STATUS.save(deps.storage, &Status::WaitCourierInDepartment)?;
+  let application = Application {
+    courier: info.sender.clone(),
+    price,
+    message,
+    encryption_key: Some(encryption_key),
+  };
 
-  let expiration_times = EXPIRATION_TIMES.load(deps.storage)?;
-  expiration_times.set_expiration_by_status(&mut deps, env)?;
+  APPLICATIONS.save(deps.storage, (order_id, &info.sender), &application)?;
 
-  let response =
-    Response::new().add_attribute("action", "sender_provided_details");
+  let response = Response::new()
+    .add_attribute("action", "courier_applied")
+    .add_attribute("order_id", order_id.to_string())
+    .add_attribute("courier", info.sender)
+    .add_attribute("price", price.unwrap_or(order.payment_amount));
 
   Ok(response)
 }
 
-pub fn universal_cancel_delivery_and_payback(
+pub fn sender_approve_courier(
   deps: DepsMut,
   env: Env,
   info: MessageInfo,
+  order_id: u64,
+  courier: Addr,
 ) -> Result<Response, ContractError> {
-  let courier = get_courier(&deps)?;
-  let owner = get_owner(&deps)?;
+  let mut order = load_order(deps.storage, order_id)?;
+  order.status.expected(Status::WaitForCourier)?;
 
-  let is_courier = info.sender == courier;
-  let is_owner = info.sender == owner;
+  let owner = check_is_owner(&order, &info)?;
 
-  if !is_owner && !is_courier {
-    return Err(ContractError::OwnerOrCourierExpected {});
+  let application = APPLICATIONS
+    .may_load(deps.storage, (order_id, &courier))?
+    .ok_or(ContractError::ApplicationNotFound {})?;
+
+  // the payment may have changed since the courier applied
+  if application.price.is_some_and(|price| price > order.payment_amount) {
+    return Err(ContractError::PriceExceedsPayment {});
   }
 
-  let can_cancel = if is_owner {
-    owner_can_cancel(&deps, &env)?
-  } else {
-    courier_can_cancel(&deps, &env)?
-  };
+  APPLICATIONS.remove(deps.storage, (order_id, &courier));
 
-  if let Some((refund_receiver, action_after_refund)) = can_cancel {
-    let (_, deposit_amount) = get_payments_info(&deps)?;
-    let contract_balance = get_contract_balance(&deps, &env)?;
+  let mut response = Response::new()
+    .add_attribute("action", "sender_approved_courier")
+    .add_attribute("order_id", order_id.to_string())
+    .add_attribute("courier", courier.clone());
 
-    let mut response = Response::new();
+  // the courier asked less than the order payment, give the difference back
+  if let Some(price) = application.price {
+    let refund_amount = order.payment_amount - price;
+    order.payment_amount = price;
 
-    // Refund messages
-    response = match refund_receiver {
-      RefundReceiver::Owner => response.add_submessage(pay_from_contract(
+    if !refund_amount.is_zero() {
+      REPLY_ORDER_ID.save(deps.storage, &order_id)?;
+      response = response.add_submessage(pay_from_contract(
         &deps,
         owner,
-        contract_balance,
+        refund_amount,
         REPLY_OWNER_REFUND,
-      )?),
+      )?);
+    }
+  }
 
-      RefundReceiver::Courier => response.add_submessage(pay_from_contract(
-        &deps,
-        courier,
-        deposit_amount,
-        REPLY_COURIER_REFUND,
-      )?),
-
-      RefundReceiver::Both => {
-        let owner_amount = contract_balance - deposit_amount;
-
-        response
-          .add_submessage(pay_from_contract(
-            &deps,
-            owner,
-            owner_amount,
-            REPLY_OWNER_REFUND,
-          )?)
-          .add_submessage(pay_from_contract(
-            &deps,
-            courier,
-            deposit_amount,
-            REPLY_COURIER_REFUND,
-          )?)
-      }
+  // the stake of the bonded courier is locked instead of the deposit transfer
+  if order.bonded {
+    check_free_stake(deps.storage, &courier, order.deposit_amount)?;
 
-      RefundReceiver::NoOne => response,
-    };
+    STAKES.update(deps.storage, &courier, |stake| -> StdResult<_> {
+      let mut stake = stake.unwrap_or_default();
+      stake.locked += order.deposit_amount;
+      Ok(stake)
+    })?;
 
-    // actions after refund
-    response = match action_after_refund {
-      AfterRefund::SetClosed => {
-        STATUS.save(deps.storage, &Status::Closed)?;
-        response.add_attribute("action", "cancel.closed")
-      }
+    order.status = Status::WaitSenderDetails;
+  } else {
+    order.status = Status::WaitDepositByCourier;
+  }
 
-      AfterRefund::SetFailed => {
-        STATUS.save(deps.storage, &Status::Failed)?;
-        response.add_attribute("action", "cancel.failed")
-      }
+  order.courier = Some(courier);
+  order.courier_encryption_key = application.encryption_key;
 
-      AfterRefund::StartOver => {
-        STATUS.save(deps.storage, &Status::WaitForCourier)?;
-        COURIER.remove(deps.storage);
+  let expiration_times = order.expiration_times;
+  expiration_times.set_expiration_by_status(&mut order, &env);
 
-        EXACT_FROM_LOCATION.remove(deps.storage);
-        EXACT_TO_LOCATION.remove(deps.storage);
-        COMMENT.remove(deps.storage);
+  orders().save(deps.storage, order_id, &order)?;
 
-        response.add_attribute("action", "cancel.start_over")
-      }
-    };
+  Ok(response.add_attribute("payment", order.payment_amount))
+}
 
-    // Clear fixation times
-    AVAILABLE_TIME.remove(deps.storage);
-    FIXATION_TIME.remove(deps.storage);
+pub fn sender_reject_application(
+  deps: DepsMut,
+  _env: Env,
+  info: MessageInfo,
+  order_id: u64,
+  courier: Addr,
+) -> Result<Response, ContractError> {
+  let order = load_order(deps.storage, order_id)?;
+  check_is_owner(&order, &info)?;
 
-    Ok(response.set_data(to_binary(&true)?))
-  } else {
-    Ok(Response::new().set_data(to_binary(&false)?))
+  if !APPLICATIONS.has(deps.storage, (order_id, &courier)) {
+    return Err(ContractError::ApplicationNotFound {});
   }
+
+  APPLICATIONS.remove(deps.storage, (order_id, &courier));
+
+  let response = Response::new()
+    .add_attribute("action", "sender_rejected_application")
+    .add_attribute("order_id", order_id.to_string())
+    .add_attribute("courier", courier);
+
+  Ok(response)
 }
 
-pub fn handle_reply_transfer_refund(
-  _deps: DepsMut,
+// Orders migrated from 0.1.x have the approved courier without the key, the
+// key cannot be replaced, so the details are encrypted to a single key
+pub fn courier_set_encryption_key(
+  deps: DepsMut,
   _env: Env,
-  msg: Reply,
-  reply_id: u64,
-) -> StdResult<Response> {
-  // @TODO: check if additional event checks need to be done
-  let _reply = msg.result.into_result().map_err(StdError::generic_err)?;
+  info: MessageInfo,
+  order_id: u64,
+  encryption_key: EncryptionKey,
+) -> Result<Response, ContractError> {
+  let mut order = load_order(deps.storage, order_id)?;
+
+  if !matches!(
+    order.status,
+    Status::WaitDepositByCourier | Status::WaitSenderDetails
+  ) {
+    return Err(ContractError::UnexpectedStatus(
+      order.status,
+      Status::WaitSenderDetails,
+    ));
+  }
 
-  let refund_receiver = if reply_id == REPLY_OWNER_REFUND {
-    "owner"
-  } else {
-    "courier"
-  };
+  let courier = check_is_courier(&order, &info)?;
+
+  if order.courier_encryption_key.is_some() {
+    return Err(ContractError::EncryptionKeyAlreadySet {});
+  }
+
+  check_encryption_key(&encryption_key)?;
+
+  order.courier_encryption_key = Some(encryption_key);
+  orders().save(deps.storage, order_id, &order)?;
 
   let response = Response::new()
-    .add_attribute("action", "refund_completed")
-    .add_attribute("receiver", refund_receiver)
-    .set_data(to_binary(&true)?);
+    .add_attribute("action", "courier_set_encryption_key")
+    .add_attribute("order_id", order_id.to_string())
+    .add_attribute("courier", courier)
+    .set_data(to_json_binary(&true)?);
 
   Ok(response)
 }
 
-pub fn sender_gave_parcel_to_courier(
-  mut deps: DepsMut,
+pub fn sender_set_details(
+  deps: DepsMut,
   env: Env,
   info: MessageInfo,
+  order_id: u64,
+  details: Envelope,
 ) -> Result<Response, ContractError> {
-  let status = STATUS.load(deps.storage)?;
-  status.expected(Status::WaitCourierInDepartment)?;
-  check_is_owner(&deps, &info)?;
+  let mut order = load_order(deps.storage, order_id)?;
+  order.status.expected(Status::WaitSenderDetails)?;
+
+  check_is_owner(&order, &info)?;
+
+  let encryption_key = order
+    .courier_encryption_key
+    .as_ref()
+    .ok_or(ContractError::EncryptionKeyNotSet {})?;
 
-  STATUS.save(deps.storage, &Status::InProgress)?;
+  check_envelope(&details, encryption_key)?;
 
-  let expiration_times = EXPIRATION_TIMES.load(deps.storage)?;
-  expiration_times.set_expiration_by_status(&mut deps, env)?;
+  order.details = Some(details);
+  order.status = Status::WaitCourierInDepartment;
 
-  let courier = get_courier(&deps)?;
+  let expiration_times = order.expiration_times;
+  expiration_times.set_expiration_by_status(&mut order, &env);
+
+  orders().save(deps.storage, order_id, &order)?;
 
   let response = Response::new()
-    .add_attribute("action", "parcel_gave_to_courier")
-    .add_attribute("courier", courier)
-    .set_data(to_binary(&true)?);
+    .add_attribute("action", "sender_provided_details")
+    .add_attribute("order_id", order_id.to_string());
 
   Ok(response)
 }
 
-pub fn courier_confirm_delivery(
+pub fn universal_cancel_delivery_and_payback(
   deps: DepsMut,
   env: Env,
   info: MessageInfo,
-  sign: String,
+  order_id: u64,
 ) -> Result<Response, ContractError> {
-  let status = STATUS.load(deps.storage)?;
-  status.expected(Status::InProgress)?;
+  let order = load_order(deps.storage, order_id)?;
+  let owner = order.owner.clone();
 
-  let (payment_amount, deposit_amount) = get_payments_info(&deps)?;
-  let courier = check_is_courier(&deps, &info)?;
+  let is_courier = order.courier.as_ref() == Some(&info.sender);
+  let is_owner = info.sender == owner;
 
-  check_courier_signature(
-    &deps,
-    env.contract.address.to_string(),
-    sign,
-    CONFIRM_PUBLIC_KEY.load(deps.storage)?,
+  if !is_owner && !is_courier {
+    return Err(ContractError::OwnerOrCourierExpected {});
+  }
+
+  let can_cancel = if is_owner {
+    owner_can_cancel(&order, &env)
+  } else {
+    courier_can_cancel(&order, &env)
+  };
+
+  if let Some((refund_receiver, action_after_refund)) = can_cancel {
+    cancel_and_payback(
+      deps,
+      order_id,
+      order,
+      refund_receiver,
+      action_after_refund,
+      Some(info.sender),
+      None,
+    )
+  } else {
+    Ok(Response::new().set_data(to_json_binary(&false)?))
+  }
+}
+
+pub fn anyone_settle_expired_order(
+  deps: DepsMut,
+  env: Env,
+  info: MessageInfo,
+  order_id: u64,
+) -> Result<Response, ContractError> {
+  let order = load_order(deps.storage, order_id)?;
+
+  let (refund_receiver, action_after_refund) =
+    can_settle(&order, &env).ok_or(ContractError::NotExpiredYet {})?;
+
+  let bounty = SETTLE_BOUNTY.may_load(deps.storage)?.unwrap_or_default();
+
+  let response = cancel_and_payback(
+    deps,
+    order_id,
+    order,
+    refund_receiver,
+    action_after_refund,
+    None,
+    Some((info.sender.clone(), bounty)),
   )?;
 
-  let transfer_msg = pay_from_contract(
-    &deps,
-    courier,
-    payment_amount + deposit_amount,
-    REPLY_PAYMENT_TO_COURIER,
+  Ok(response.add_attribute("settled_by", info.sender))
+}
+
+// Refund the order funds and move the order to the next status, if `bounty` is
+// set, its amount is taken from the refunds and sent to the given address.
+// `canceled_by` is the owner or the courier, empty if the order is settled.
+fn cancel_and_payback(
+  deps: DepsMut,
+  order_id: u64,
+  mut order: Order,
+  refund_receiver: RefundReceiver,
+  action_after_refund: AfterRefund,
+  canceled_by: Option<Addr>,
+  bounty: Option<(Addr, Uint128)>,
+) -> Result<Response, ContractError> {
+  update_reputation_on_cancel(
+    deps.storage,
+    &order,
+    canceled_by,
+    &action_after_refund,
   )?;
 
-  let response = Response::new()
-    .add_submessage(transfer_msg)
-    .set_data(to_binary(&true)?);
+  let owner = order.owner.clone();
+  let deposit_amount = order.deposit_amount;
+  let locked_amount = order.locked_amount();
 
-  Ok(response)
-}
+  // the parcel is lost on the way, it is covered by the insurance pool
+  let is_parcel_lost = order.status == Status::InProgress
+    && action_after_refund == AfterRefund::SetFailed;
 
-pub fn handle_reply_transfer_payment_to_courier(
-  mut deps: DepsMut,
-  env: Env,
-  msg: Reply,
-) -> StdResult<Response> {
-  // @TODO: check whether it is necessary to do an additional check of events that the payment has passed
-  let _reply = msg.result.into_result().map_err(StdError::generic_err)?;
+  let mut response =
+    Response::new().add_attribute("order_id", order_id.to_string());
 
-  let courier =
-    get_courier(&deps).or(Err(StdError::generic_err("Courier not found")))?;
+  // Refund messages
+  let mut refunds = match refund_receiver {
+    RefundReceiver::Owner => vec![(owner, locked_amount, REPLY_OWNER_REFUND)],
 
-  STATUS.save(deps.storage, &Status::Delivered)?;
+    RefundReceiver::Courier => {
+      vec![(get_courier(&order)?, deposit_amount, REPLY_COURIER_REFUND)]
+    }
 
-  // clear expiration after delivery
-  let expiration_times = EXPIRATION_TIMES.load(deps.storage)?;
-  expiration_times
-    .set_expiration_by_status(&mut deps, env)
-    .or(Err(StdError::generic_err("Cannot set expiration")))?;
+    RefundReceiver::Both => vec![
+      (owner, locked_amount - deposit_amount, REPLY_OWNER_REFUND),
+      (get_courier(&order)?, deposit_amount, REPLY_COURIER_REFUND),
+    ],
+
+    RefundReceiver::NoOne => vec![],
+  };
+
+  if let Some((receiver, amount)) = bounty {
+    let mut rest = amount;
+
+    for (_, refund_amount, _) in refunds.iter_mut() {
+      let part = rest.min(*refund_amount);
+      *refund_amount -= part;
+      rest -= part;
+    }
+
+    refunds.push((receiver, amount - rest, REPLY_SETTLE_BOUNTY));
+  }
+
+  // platform fee from the payment returned to the owner, if configured
+  let fee = FEE
+    .may_load(deps.storage)?
+    .filter(|fee| fee.on_cancel == FeeOnCancel::Charge);
+
+  if let Some(fee) = fee {
+    let owner_refund = refunds
+      .iter_mut()
+      .find(|(_, _, reply_id)| *reply_id == REPLY_OWNER_REFUND);
+
+    if let Some((_, owner_amount, _)) = owner_refund {
+      // parcels of the batch order delivered before are charged on delivery
+      let payment = order.total_payment() - order.paid_payment;
+      let fee_amount = fee.fee_for(payment).min(*owner_amount);
+      *owner_amount -= fee_amount;
+
+      refunds.push((fee.collector, fee_amount, REPLY_FEE_TO_COLLECTOR));
+      response = response.add_attribute("fee", fee_amount);
+    }
+  }
+
+  // the deposit of the bonded courier is in the stake, the part returned to
+  // the courier is unlocked, the rest is slashed and paid from the stake
+  let collateral = order.collateral();
+
+  if !collateral.is_zero() {
+    let returned = refunds
+      .iter()
+      .filter(|(_, _, reply_id)| *reply_id == REPLY_COURIER_REFUND)
+      .map(|(_, amount, _)| *amount)
+      .sum();
+
+    refunds.retain(|(_, _, reply_id)| *reply_id != REPLY_COURIER_REFUND);
+
+    let slashed =
+      release_stake(deps.storage, &get_courier(&order)?, collateral, returned)?;
+    response = response.add_attribute("slashed", slashed);
+  }
+
+  if is_parcel_lost && order.declared_value.is_some() {
+    let pool = INSURANCE_POOL.may_load(deps.storage)?.unwrap_or_default();
+    let payout = order.insured_value().min(pool);
+
+    INSURANCE_POOL.save(deps.storage, &(pool - payout))?;
+
+    refunds.push((order.owner.clone(), payout, REPLY_INSURANCE_PAYOUT));
+    response = response.add_attribute("insurance_payout", payout);
+  }
+
+  for (receiver, amount, reply_id) in refunds {
+    // nothing to transfer, for example if the order was not paid yet
+    if amount.is_zero() {
+      continue;
+    }
+
+    response = response
+      .add_submessage(pay_from_contract(&deps, receiver, amount, reply_id)?);
+  }
+
+  // actions after refund
+  response = match action_after_refund {
+    AfterRefund::SetClosed => {
+      order.status = Status::Closed;
+      response.add_attribute("action", "cancel.closed")
+    }
+
+    AfterRefund::SetFailed => {
+      order.status = Status::Failed;
+      response.add_attribute("action", "cancel.failed")
+    }
+
+    AfterRefund::StartOver => {
+      order.status = Status::WaitForCourier;
+      order.courier = None;
+      // coupons of the previous courier are no longer valid
+      order.nonce += 1;
+      order.legacy_coupon = false;
+
+      order.courier_encryption_key = None;
+      order.details = None;
+
+      order.parcel_issued = false;
+      order.parcel_picked_up = false;
+
+      // the order payment may have been reduced to the price of the courier
+      clear_applications(deps.storage, order_id)?;
+
+      response.add_attribute("action", "cancel.start_over")
+    }
+  };
+
+  // Clear fixation times
+  order.available_time = None;
+  order.fixation_time = None;
+
+  orders().save(deps.storage, order_id, &order)?;
+  REPLY_ORDER_ID.save(deps.storage, &order_id)?;
+
+  Ok(response.set_data(to_json_binary(&true)?))
+}
+
+fn update_reputation_on_cancel(
+  storage: &mut dyn Storage,
+  order: &Order,
+  canceled_by: Option<Addr>,
+  action_after_refund: &AfterRefund,
+) -> StdResult<()> {
+  let stage = &order.status;
+
+  if let Some(canceled_by) = canceled_by {
+    update_reputation(storage, &canceled_by, |reputation| {
+      let stats = if canceled_by == order.owner {
+        &mut reputation.as_sender
+      } else {
+        &mut reputation.as_courier
+      };
+
+      stats.cancellations.add(stage);
+    })?;
+  }
+
+  if *action_after_refund != AfterRefund::SetFailed {
+    return Ok(());
+  }
+
+  // the party who did not act in time at the stage
+  match (stage, &order.courier) {
+    (Status::WaitSenderDetails, _) => {
+      update_reputation(storage, &order.owner, |reputation| {
+        reputation.as_sender.failed += 1
+      })
+    }
+
+    (Status::WaitDepositByCourier | Status::InProgress, Some(courier)) => {
+      update_reputation(storage, courier, |reputation| {
+        reputation.as_courier.failed += 1
+      })
+    }
+
+    _ => Ok(()),
+  }
+}
+
+pub fn handle_reply_transfer_refund(
+  deps: DepsMut,
+  _env: Env,
+  msg: Reply,
+  reply_id: u64,
+) -> StdResult<Response> {
+  // @TODO: check if additional event checks need to be done
+  let _reply = msg.result.into_result().map_err(StdError::generic_err)?;
+
+  let order_id = REPLY_ORDER_ID.load(deps.storage)?;
+
+  let refund_receiver = match reply_id {
+    REPLY_OWNER_REFUND => "owner",
+    REPLY_SETTLE_BOUNTY => "settler",
+    REPLY_FEE_TO_COLLECTOR => "fee_collector",
+    REPLY_INSURANCE_PAYOUT => "insured_owner",
+    _ => "courier",
+  };
+
+  let response = Response::new()
+    .add_attribute("action", "refund_completed")
+    .add_attribute("order_id", order_id.to_string())
+    .add_attribute("receiver", refund_receiver)
+    .set_data(to_json_binary(&true)?);
+
+  Ok(response)
+}
+
+pub fn universal_open_dispute(
+  deps: DepsMut,
+  env: Env,
+  info: MessageInfo,
+  order_id: u64,
+  reason: String,
+) -> Result<Response, ContractError> {
+  let mut order = load_order(deps.storage, order_id)?;
+
+  // the parcel may be with the courier before the delivery starts, if only
+  // the owner confirmed the handoff
+  let is_issued = order.status == Status::WaitCourierInDepartment
+    && order.parcel_issued;
+
+  if !is_issued {
+    order.status.expected(Status::InProgress)?;
+  }
+
+  if ARBITER.may_load(deps.storage)?.is_none() {
+    return Err(ContractError::ArbiterNotSet {});
+  }
+
+  let is_courier = order.courier.as_ref() == Some(&info.sender);
+  let is_owner = info.sender == order.owner;
+
+  if !is_owner && !is_courier {
+    return Err(ContractError::OwnerOrCourierExpected {});
+  }
+
+  check_message_length(&reason)?;
+
+  let dispute = Dispute {
+    opened_by: info.sender.clone(),
+    opened_at: env.block.time,
+    reason,
+    ruling: None,
+  };
+
+  DISPUTES.save(deps.storage, order_id, &dispute)?;
+
+  // funds are frozen until the arbiter decision, so clear the expiration
+  order.status = Status::Disputed;
+
+  let expiration_times = order.expiration_times;
+  expiration_times.set_expiration_by_status(&mut order, &env);
+
+  orders().save(deps.storage, order_id, &order)?;
+
+  let response = Response::new()
+    .add_attribute("action", "dispute_opened")
+    .add_attribute("order_id", order_id.to_string())
+    .add_attribute("opened_by", info.sender)
+    .set_data(to_json_binary(&true)?);
+
+  Ok(response)
+}
+
+pub fn arbiter_resolve_dispute(
+  deps: DepsMut,
+  _env: Env,
+  info: MessageInfo,
+  order_id: u64,
+  courier_payment: Uint128,
+  courier_deposit: Uint128,
+  reason: String,
+) -> Result<Response, ContractError> {
+  let mut order = load_order(deps.storage, order_id)?;
+  order.status.expected(Status::Disputed)?;
+
+  if ARBITER.may_load(deps.storage)?.as_ref() != Some(&info.sender) {
+    return Err(ContractError::ArbiterExpected {});
+  }
+
+  // parcels of the batch order delivered before the dispute are already paid
+  if courier_payment > order.payment_amount - order.paid_payment
+    || courier_deposit > order.deposit_amount - order.paid_deposit
+  {
+    return Err(ContractError::InvalidRuling {});
+  }
+
+  check_message_length(&reason)?;
+
+  let courier = get_courier(&order)?;
+  let mut courier_amount = courier_payment + courier_deposit;
+  let owner_amount = order.locked_amount() - courier_amount;
+
+  // the deposit of the bonded courier is returned to the stake
+  let collateral = order.collateral();
+
+  if !collateral.is_zero() {
+    release_stake(deps.storage, &courier, collateral, courier_deposit)?;
+    courier_amount = courier_payment;
+  }
+
+  let mut dispute = DISPUTES.load(deps.storage, order_id)?;
+  dispute.ruling = Some(Ruling {
+    courier_payment,
+    courier_deposit,
+    owner_amount,
+    reason,
+  });
+
+  DISPUTES.save(deps.storage, order_id, &dispute)?;
+
+  order.status = Status::Resolved;
+  orders().save(deps.storage, order_id, &order)?;
+
+  let mut response = Response::new()
+    .add_attribute("action", "dispute_resolved")
+    .add_attribute("order_id", order_id.to_string())
+    .add_attribute("courier_amount", courier_amount)
+    .add_attribute("owner_amount", owner_amount);
+
+  let payouts = [
+    (order.owner, owner_amount, REPLY_OWNER_REFUND),
+    (courier, courier_amount, REPLY_COURIER_REFUND),
+  ];
+
+  for (receiver, amount, reply_id) in payouts {
+    if amount.is_zero() {
+      continue;
+    }
+
+    response = response
+      .add_submessage(pay_from_contract(&deps, receiver, amount, reply_id)?);
+  }
+
+  REPLY_ORDER_ID.save(deps.storage, &order_id)?;
+
+  Ok(response.set_data(to_json_binary(&true)?))
+}
+
+pub fn sender_gave_parcel_to_courier(
+  deps: DepsMut,
+  env: Env,
+  info: MessageInfo,
+  order_id: u64,
+) -> Result<Response, ContractError> {
+  let mut order = load_order(deps.storage, order_id)?;
+  order.status.expected(Status::WaitCourierInDepartment)?;
+  check_is_owner(&order, &info)?;
+
+  order.parcel_issued = true;
+
+  parcel_handoff(deps, env, order_id, order, "parcel_gave_to_courier")
+}
+
+pub fn courier_picked_up_parcel(
+  deps: DepsMut,
+  env: Env,
+  info: MessageInfo,
+  order_id: u64,
+) -> Result<Response, ContractError> {
+  let mut order = load_order(deps.storage, order_id)?;
+  order.status.expected(Status::WaitCourierInDepartment)?;
+  check_is_courier(&order, &info)?;
+
+  order.parcel_picked_up = true;
+
+  parcel_handoff(deps, env, order_id, order, "courier_picked_up_parcel")
+}
+
+// The delivery timer starts only when both the owner and the courier
+// confirmed the handoff, so neither of them can start it alone
+fn parcel_handoff(
+  deps: DepsMut,
+  env: Env,
+  order_id: u64,
+  mut order: Order,
+  action: &str,
+) -> Result<Response, ContractError> {
+  let courier = get_courier(&order)?;
+
+  let is_confirmed = order.parcel_issued && order.parcel_picked_up;
+
+  if is_confirmed {
+    order.status = Status::InProgress;
+
+    let expiration_times = order.expiration_times;
+    expiration_times.set_expiration_by_status(&mut order, &env);
+  }
+
+  orders().save(deps.storage, order_id, &order)?;
+
+  let response = Response::new()
+    .add_attribute("action", action)
+    .add_attribute("order_id", order_id.to_string())
+    .add_attribute("courier", courier)
+    .add_attribute("delivery_started", is_confirmed.to_string())
+    .set_data(to_json_binary(&true)?);
+
+  Ok(response)
+}
+
+pub fn courier_confirm_delivery(
+  deps: DepsMut,
+  env: Env,
+  info: MessageInfo,
+  order_id: u64,
+  sign: String,
+  parcel: u32,
+) -> Result<Response, ContractError> {
+  let mut order = load_order(deps.storage, order_id)?;
+  order.status.expected(Status::InProgress)?;
+
+  let courier = check_is_courier(&order, &info)?;
+
+  let (confirm_key, share) = order
+    .parcel_terms(parcel)
+    .ok_or(ContractError::ParcelNotFound(parcel))?;
+
+  if order.delivered_parcels.contains(&parcel) {
+    return Err(ContractError::ParcelAlreadyDelivered(parcel));
+  }
+
+  let coupon =
+    CouponPayload::new(&env, order_id, &order, courier.clone(), parcel);
+
+  // recipients of the migrated order may hold coupons of the 0.1.x format
+  match check_courier_signature(&deps, &coupon, sign.clone(), confirm_key) {
+    Err(_) if order.legacy_coupon => check_legacy_courier_signature(
+      &deps,
+      &env.contract.address,
+      sign,
+      confirm_key,
+    )?,
+    result => result?,
+  }
+
+  // parcels are paid pro rata, the last one takes the rest of the funds
+  let is_last =
+    order.delivered_parcels.len() as u32 + 1 == order.parcels_count();
+
+  let (payment, deposit) = if is_last {
+    (
+      order.payment_amount - order.paid_payment,
+      order.deposit_amount - order.paid_deposit,
+    )
+  } else {
+    let deposit = order
+      .deposit_amount
+      .checked_multiply_ratio(share, order.payment_amount)
+      .unwrap_or_default();
+
+    (share, deposit)
+  };
+
+  // the deposit of the bonded courier is unlocked in the stake
+  let deposit_transfer = if order.bonded {
+    release_stake(deps.storage, &courier, deposit, deposit)?;
+    Uint128::zero()
+  } else {
+    deposit
+  };
+
+  order.delivered_parcels.push(parcel);
+  order.paid_payment += payment;
+  order.paid_deposit += deposit;
+
+  orders().save(deps.storage, order_id, &order)?;
+
+  let fee = FEE.may_load(deps.storage)?;
+  let fee_amount = fee
+    .as_ref()
+    .map(|fee| fee.fee_for(payment))
+    .unwrap_or_default();
+
+  let courier_amount = payment + deposit_transfer - fee_amount;
+
+  let mut transfer_msgs = vec![];
+
+  if !courier_amount.is_zero() {
+    transfer_msgs.push(pay_from_contract(
+      &deps,
+      courier,
+      courier_amount,
+      REPLY_PAYMENT_TO_COURIER,
+    )?);
+  }
+
+  if let Some(fee) = fee.filter(|_| !fee_amount.is_zero()) {
+    transfer_msgs.push(pay_from_contract(
+      &deps,
+      fee.collector,
+      fee_amount,
+      REPLY_FEE_TO_COLLECTOR,
+    )?);
+  }
+
+  REPLY_ORDER_ID.save(deps.storage, &order_id)?;
+
+  // nothing to transfer to the courier, for example for the zero share of
+  // the parcel or the bonded order, so there is no reply to complete it
+  let response = if courier_amount.is_zero() {
+    courier_paid(deps, env, order_id)?
+  } else {
+    Response::new()
+      .add_attribute("order_id", order_id.to_string())
+      .set_data(to_json_binary(&true)?)
+  };
+
+  Ok(
+    response
+      .add_submessages(transfer_msgs)
+      .add_attribute("parcel", parcel.to_string())
+      .add_attribute("courier_amount", courier_amount)
+      .add_attribute("fee", fee_amount),
+  )
+}
+
+pub fn handle_reply_transfer_payment_to_courier(
+  deps: DepsMut,
+  env: Env,
+  msg: Reply,
+) -> StdResult<Response> {
+  // @TODO: check whether it is necessary to do an additional check of events that the payment has passed
+  let _reply = msg.result.into_result().map_err(StdError::generic_err)?;
+
+  let order_id = REPLY_ORDER_ID.load(deps.storage)?;
+  courier_paid(deps, env, order_id)
+}
+
+fn courier_paid(deps: DepsMut, env: Env, order_id: u64) -> StdResult<Response> {
+  let mut order = orders().load(deps.storage, order_id)?;
+
+  let courier =
+    get_courier(&order).or(Err(StdError::generic_err("Courier not found")))?;
+
+  // other parcels of the batch order are still on the way
+  if !order.is_all_parcels_delivered() {
+    let response = Response::new()
+      .add_attribute("action", "batch_parcel_delivered")
+      .add_attribute("order_id", order_id.to_string())
+      .add_attribute("courier", courier)
+      .set_data(to_json_binary(&true)?);
+
+    return Ok(response);
+  }
+
+  update_reputation(deps.storage, &courier, |reputation| {
+    reputation.as_courier.completed += 1;
+    reputation.as_courier.volume += order.payment_amount;
+  })?;
+
+  // the parcel is handed off to the next relay leg
+  if !order.next_legs.is_empty() {
+    return start_next_leg(deps, env, order_id, order, courier);
+  }
+
+  order.status = Status::Delivered;
+
+  update_reputation(deps.storage, &order.owner, |reputation| {
+    reputation.as_sender.completed += 1;
+    reputation.as_sender.volume += order.payment_amount;
+  })?;
+
+  // after delivery the expiration is the end of the review time
+  let expiration_times = order.expiration_times;
+  expiration_times.set_expiration_by_status(&mut order, &env);
+
+  orders().save(deps.storage, order_id, &order)?;
 
   let response = Response::new()
     .add_attribute("action", "parcel_delivered")
+    .add_attribute("order_id", order_id.to_string())
+    .add_attribute("courier", courier)
+    .set_data(to_json_binary(&true)?);
+
+  Ok(response)
+}
+
+// Terms of the next leg become the terms of the order, and the order waits
+// for a courier again, the payment of the leg is already held by the contract
+fn start_next_leg(
+  deps: DepsMut,
+  env: Env,
+  order_id: u64,
+  mut order: Order,
+  previous_courier: Addr,
+) -> StdResult<Response> {
+  let leg = order.next_legs.remove(0);
+
+  order.leg += 1;
+  order.rough_from_location =
+    std::mem::replace(&mut order.rough_to_location, leg.to);
+  order.confirm_public_key = leg.confirm_public_key;
+  order.deposit_amount = leg.deposit_amount;
+  order.payment_amount = leg.payment_amount;
+
+  order.status = Status::WaitForCourier;
+  order.courier = None;
+  // coupons of the previous leg are no longer valid
+  order.nonce += 1;
+  order.legacy_coupon = false;
+
+  order.courier_encryption_key = None;
+  order.details = None;
+
+  order.delivered_parcels.clear();
+  order.paid_payment = Uint128::zero();
+  order.paid_deposit = Uint128::zero();
+
+  order.parcel_issued = false;
+  order.parcel_picked_up = false;
+
+  let expiration_times = order.expiration_times;
+  expiration_times.set_expiration_by_status(&mut order, &env);
+
+  // applications of the previous leg
+  clear_applications(deps.storage, order_id)?;
+  orders().save(deps.storage, order_id, &order)?;
+
+  let response = Response::new()
+    .add_attribute("action", "leg_delivered")
+    .add_attribute("order_id", order_id.to_string())
+    .add_attribute("courier", previous_courier)
+    .add_attribute("next_leg", order.leg.to_string())
+    .set_data(to_json_binary(&true)?);
+
+  Ok(response)
+}
+
+pub fn universal_leave_review(
+  deps: DepsMut,
+  env: Env,
+  info: MessageInfo,
+  order_id: u64,
+  rating: u8,
+  review: Option<String>,
+) -> Result<Response, ContractError> {
+  let order = load_order(deps.storage, order_id)?;
+  order.status.expected(Status::Delivered)?;
+
+  let courier = get_courier(&order)?;
+
+  // each party reviews the other one
+  let subject = if info.sender == order.owner {
+    courier.clone()
+  } else if info.sender == courier {
+    order.owner.clone()
+  } else {
+    return Err(ContractError::OwnerOrCourierExpected {});
+  };
+
+  if is_time_over(&order, &env) {
+    return Err(ContractError::ReviewTimeOver {});
+  }
+
+  if !(MIN_RATING..=MAX_RATING).contains(&rating) {
+    return Err(ContractError::InvalidRating(MIN_RATING, MAX_RATING));
+  }
+
+  if let Some(review) = &review {
+    check_message_length(review)?;
+  }
+
+  if REVIEWS.has(deps.storage, (&subject, order_id)) {
+    return Err(ContractError::AlreadyReviewed {});
+  }
+
+  let review = Review {
+    order_id,
+    author: info.sender.clone(),
+    rating,
+    text: review,
+    created_at: env.block.time,
+  };
+
+  REVIEWS.save(deps.storage, (&subject, order_id), &review)?;
+
+  update_reputation(deps.storage, &subject, |reputation| {
+    let stats = if subject == courier {
+      &mut reputation.as_courier
+    } else {
+      &mut reputation.as_sender
+    };
+
+    stats.ratings.add(rating);
+  })?;
+
+  let response = Response::new()
+    .add_attribute("action", "review_left")
+    .add_attribute("order_id", order_id.to_string())
+    .add_attribute("author", info.sender)
+    .add_attribute("subject", subject)
+    .add_attribute("rating", rating.to_string())
+    .set_data(to_json_binary(&true)?);
+
+  Ok(response)
+}
+
+pub fn anyone_fund_insurance_pool(
+  mut deps: DepsMut,
+  env: Env,
+  info: MessageInfo,
+  amount: Uint128,
+) -> Result<Response, ContractError> {
+  let transfer_msg =
+    pay_to_contract_by_sender(&deps, &env, &info, amount, REPLY_POOL_FUNDING)?;
+
+  // the transfer fails the whole message, so the pool is funded right away
+  let response = insurance_pool_funded(deps.branch(), info.sender, amount)?;
+
+  Ok(match transfer_msg {
+    Some(transfer_msg) => response.add_submessage(transfer_msg),
+    None => response,
+  })
+}
+
+fn insurance_pool_funded(
+  deps: DepsMut,
+  sender: Addr,
+  amount: Uint128,
+) -> Result<Response, ContractError> {
+  if amount.is_zero() {
+    return Err(ContractError::ZeroAmount {});
+  }
+
+  let pool = INSURANCE_POOL.may_load(deps.storage)?.unwrap_or_default();
+  INSURANCE_POOL.save(deps.storage, &(pool + amount))?;
+
+  let response = Response::new()
+    .add_attribute("action", "insurance_pool_funded")
+    .add_attribute("sender", sender)
+    .add_attribute("amount", amount)
+    .add_attribute("pool", pool + amount)
+    .set_data(to_json_binary(&true)?);
+
+  Ok(response)
+}
+
+// Replies of transfers not related to orders, state is updated before them
+pub fn handle_reply_transfer_funds(
+  _deps: DepsMut,
+  _env: Env,
+  msg: Reply,
+  reply_id: u64,
+) -> StdResult<Response> {
+  // @TODO: check if additional event checks need to be done
+  let _reply = msg.result.into_result().map_err(StdError::generic_err)?;
+
+  let action = match reply_id {
+    REPLY_POOL_FUNDING => "pool_funding_completed",
+    REPLY_STAKE => "stake_completed",
+    _ => "unstake_completed",
+  };
+
+  let response = Response::new()
+    .add_attribute("action", action)
+    .set_data(to_json_binary(&true)?);
+
+  Ok(response)
+}
+
+pub fn courier_stake(
+  mut deps: DepsMut,
+  env: Env,
+  info: MessageInfo,
+  amount: Uint128,
+) -> Result<Response, ContractError> {
+  let transfer_msg =
+    pay_to_contract_by_sender(&deps, &env, &info, amount, REPLY_STAKE)?;
+
+  // the transfer fails the whole message, so the stake is added right away
+  let response = stake_received(deps.branch(), info.sender, amount)?;
+
+  Ok(match transfer_msg {
+    Some(transfer_msg) => response.add_submessage(transfer_msg),
+    None => response,
+  })
+}
+
+fn stake_received(
+  deps: DepsMut,
+  courier: Addr,
+  amount: Uint128,
+) -> Result<Response, ContractError> {
+  if amount.is_zero() {
+    return Err(ContractError::ZeroAmount {});
+  }
+
+  let mut stake = STAKES.may_load(deps.storage, &courier)?.unwrap_or_default();
+  stake.amount += amount;
+  STAKES.save(deps.storage, &courier, &stake)?;
+
+  let response = Response::new()
+    .add_attribute("action", "courier_staked")
     .add_attribute("courier", courier)
-    .set_data(to_binary(&true)?);
+    .add_attribute("amount", amount)
+    .add_attribute("stake", stake.amount)
+    .set_data(to_json_binary(&true)?);
+
+  Ok(response)
+}
+
+pub fn courier_unstake(
+  deps: DepsMut,
+  _env: Env,
+  info: MessageInfo,
+  amount: Uint128,
+) -> Result<Response, ContractError> {
+  if amount.is_zero() {
+    return Err(ContractError::ZeroAmount {});
+  }
+
+  let mut stake = STAKES
+    .may_load(deps.storage, &info.sender)?
+    .unwrap_or_default();
+
+  // the stake locked in orders cannot be withdrawn
+  if amount > stake.free() {
+    return Err(ContractError::InsufficientStake(stake.free()));
+  }
+
+  stake.amount -= amount;
+  STAKES.save(deps.storage, &info.sender, &stake)?;
+
+  let transfer_msg =
+    pay_from_contract(&deps, info.sender.clone(), amount, REPLY_UNSTAKE)?;
+
+  let response = Response::new()
+    .add_submessage(transfer_msg)
+    .add_attribute("action", "courier_unstaked")
+    .add_attribute("courier", info.sender)
+    .add_attribute("amount", amount)
+    .add_attribute("stake", stake.amount)
+    .set_data(to_json_binary(&true)?);
 
   Ok(response)
 }
@@ -0,0 +1,225 @@
+use std::str::FromStr;
+
+use cosmwasm_std::{
+  SignedDecimal,
+  Attribute,
+  Timestamp,
+  Uint128,
+  DepsMut,
+  Addr,
+  Env,
+};
+use cw_storage_plus::Item;
+use cw_asset::AssetInfo;
+use semver::Version;
+
+use crate::error::ContractError;
+use crate::models::*;
+use crate::state::*;
+use crate::utils::check_area;
+
+// State transform from the previous version of the contract
+pub struct Migration {
+  // contract version which introduced the state change
+  pub version: &'static str,
+  // returns attributes for the migrate response
+  pub run: fn(&mut DepsMut, &Env) -> Result<Vec<Attribute>, ContractError>,
+}
+
+// Registered migrations, in the order of versions
+pub const MIGRATIONS: &[Migration] = &[Migration {
+  version: "0.2.0",
+  run: legacy_single_order_to_registry,
+}];
+
+// Run migrations with versions after `from` and up to `to`, returns versions
+// of applied migrations and their attributes
+pub fn run_migrations(
+  deps: &mut DepsMut,
+  env: &Env,
+  from: &Version,
+  to: &Version,
+) -> Result<(Vec<&'static str>, Vec<Attribute>), ContractError> {
+  let mut applied = vec![];
+  let mut attributes = vec![];
+
+  for migration in MIGRATIONS {
+    let version = parse_version(migration.version)?;
+
+    if &version > from && &version <= to {
+      attributes.extend((migration.run)(deps, env)?);
+      applied.push(migration.version);
+    }
+  }
+
+  Ok((applied, attributes))
+}
+
+pub fn parse_version(version: &str) -> Result<Version, ContractError> {
+  Version::parse(version)
+    .or(Err(ContractError::InvalidVersion(version.to_string())))
+}
+
+// Storage of the 0.1.x contract, one delivery per contract instance
+mod legacy {
+  use super::*;
+
+  pub const CONFIRM_PUBLIC_KEY: Item<String> = Item::new("confirm_public_key");
+  pub const EXPIRATION_TIMES: Item<ExpirationTimes> =
+    Item::new("expiration_times");
+  pub const FIXATION_TIME: Item<Timestamp> = Item::new("fixation_time");
+  pub const AVAILABLE_TIME: Item<u64> = Item::new("available_time");
+  pub const EXACT_FROM_LOCATION: Item<String> =
+    Item::new("from_exact_location");
+  pub const EXACT_TO_LOCATION: Item<String> = Item::new("to_exact_location");
+  pub const COMMENT: Item<String> = Item::new("comment");
+  pub const ROUGH_FROM_LOCATION: Item<String> =
+    Item::new("from_rough_location");
+  pub const ROUGH_TO_LOCATION: Item<String> = Item::new("to_rough_location");
+  pub const DEPOSIT_AMOUNT: Item<Uint128> = Item::new("deposit_amount");
+  pub const PAYMENT_AMOUNT: Item<Uint128> = Item::new("payment_amount");
+  pub const COURIER: Item<Addr> = Item::new("courier");
+  pub const OWNER: Item<Addr> = Item::new("owner");
+  pub const TOKEN: Item<Addr> = Item::new("token");
+  pub const STATUS: Item<Status> = Item::new("status");
+
+  // Rough locations were free-form strings, clients filled them with a
+  // geohash or with corners of a box like `[[lat,lng],[lat,lng]]`.
+  // Anything else becomes the whole map.
+  pub fn parse_area(location: &str) -> Area {
+    let corners = location
+      .split(',')
+      .map(|part| SignedDecimal::from_str(part.trim_matches(['[', ']', ' '])))
+      .collect::<Result<Vec<_>, _>>();
+
+    let area = match corners.as_deref() {
+      Ok([south, west, north, east]) => Area::BoundingBox {
+        south_west: Coordinates {
+          lat: *south,
+          lng: *west,
+        },
+        north_east: Coordinates {
+          lat: *north,
+          lng: *east,
+        },
+      },
+      _ => Area::Geohash(location.to_string()),
+    };
+
+    check_area(area).unwrap_or_else(|_| whole_map())
+  }
+
+  fn whole_map() -> Area {
+    let degrees = |value: i128| SignedDecimal::from_atomics(value, 0).unwrap();
+
+    Area::BoundingBox {
+      south_west: Coordinates {
+        lat: degrees(-90),
+        lng: degrees(-180),
+      },
+      north_east: Coordinates {
+        lat: degrees(90),
+        lng: degrees(180),
+      },
+    }
+  }
+}
+
+// 0.2.0: singleton items of the delivery become the order with id 1 in the
+// orders registry, the cw20 token becomes the payment asset, rough locations
+// become areas
+fn legacy_single_order_to_registry(
+  deps: &mut DepsMut,
+  _env: &Env,
+) -> Result<Vec<Attribute>, ContractError> {
+  let storage = &mut *deps.storage;
+  let status = legacy::STATUS.load(storage)?;
+
+  // recipients of the approved courier may already hold coupons signed over
+  // sha256 of the contract address, they are accepted until the order starts over
+  let legacy_coupon = matches!(
+    status,
+    Status::WaitDepositByCourier
+      | Status::WaitSenderDetails
+      | Status::WaitCourierInDepartment
+      | Status::InProgress
+  );
+
+  let order = Order {
+    owner: legacy::OWNER.load(storage)?,
+    courier: legacy::COURIER.may_load(storage)?,
+    status,
+
+    // keys of the previous version are always secp256k1
+    confirm_public_key: ConfirmPublicKey::Secp256k1(
+      legacy::CONFIRM_PUBLIC_KEY.load(storage)?,
+    ),
+    nonce: 0,
+    legacy_coupon,
+    expiration_times: legacy::EXPIRATION_TIMES.load(storage)?,
+    fixation_time: legacy::FIXATION_TIME.may_load(storage)?,
+    available_time: legacy::AVAILABLE_TIME.may_load(storage)?,
+
+    // details of the previous version are plaintext strings, they are not
+    // kept, the courier can read them from the transaction history. The
+    // approved courier registers the key with `SetEncryptionKey`.
+    courier_encryption_key: None,
+    details: None,
+
+    rough_from_location: legacy::parse_area(
+      &legacy::ROUGH_FROM_LOCATION.load(storage)?,
+    ),
+    rough_to_location: legacy::parse_area(
+      &legacy::ROUGH_TO_LOCATION.load(storage)?,
+    ),
+    courier_requirement: None,
+
+    leg: 0,
+    next_legs: vec![],
+
+    parcels: vec![],
+    delivered_parcels: vec![],
+    paid_payment: Uint128::zero(),
+    paid_deposit: Uint128::zero(),
+
+    declared_value: None,
+    insurance_premium: Uint128::zero(),
+
+    bonded: false,
+
+    parcel_issued: false,
+    parcel_picked_up: false,
+
+    deposit_amount: legacy::DEPOSIT_AMOUNT.load(storage)?,
+    payment_amount: legacy::PAYMENT_AMOUNT.load(storage)?,
+  };
+
+  let token = legacy::TOKEN.load(storage)?;
+
+  orders().save(storage, 1, &order)?;
+  ORDER_COUNT.save(storage, &1)?;
+  PAYMENT_ASSET.save(storage, &AssetInfo::cw20(token))?;
+  SETTLE_BOUNTY.save(storage, &Uint128::zero())?;
+  INSURANCE_POOL.save(storage, &Uint128::zero())?;
+
+  legacy::CONFIRM_PUBLIC_KEY.remove(storage);
+  legacy::EXPIRATION_TIMES.remove(storage);
+  legacy::FIXATION_TIME.remove(storage);
+  legacy::AVAILABLE_TIME.remove(storage);
+  legacy::EXACT_FROM_LOCATION.remove(storage);
+  legacy::EXACT_TO_LOCATION.remove(storage);
+  legacy::COMMENT.remove(storage);
+  legacy::ROUGH_FROM_LOCATION.remove(storage);
+  legacy::ROUGH_TO_LOCATION.remove(storage);
+  legacy::DEPOSIT_AMOUNT.remove(storage);
+  legacy::PAYMENT_AMOUNT.remove(storage);
+  legacy::COURIER.remove(storage);
+  legacy::OWNER.remove(storage);
+  legacy::TOKEN.remove(storage);
+  legacy::STATUS.remove(storage);
+
+  Ok(vec![Attribute::new(
+    "legacy_coupon",
+    legacy_coupon.to_string(),
+  )])
+}
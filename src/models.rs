@@ -1,8 +1,17 @@
-use cosmwasm_std::{DepsMut, Env, Timestamp};
+use cosmwasm_std::{
+  to_json_vec,
+  SignedDecimal,
+  StdResult,
+  Uint128,
+  Binary,
+  Addr,
+  Env,
+};
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 use schemars::JsonSchema;
 use crate::ContractError;
-use crate::state::*;
+use crate::state::Order;
 
 // Refund receiver after cancel send request
 #[derive(Serialize, Deserialize, Clone, PartialEq, JsonSchema, Debug)]
@@ -56,6 +65,11 @@ pub enum Status {
   Failed,
   // Send request canceled by sender, funds returned to his wallet, contract closed.
   Closed,
+  // The owner or the courier opened a dispute during the delivery, funds are
+  // frozen until the arbiter rules how to split the payment and the deposit
+  Disputed,
+  // The arbiter resolved the dispute, funds are sent to the parties
+  Resolved,
 }
 
 impl Status {
@@ -68,6 +82,11 @@ impl Status {
       Ok(true)
     }
   }
+
+  // key of the status in the orders index
+  pub fn key(&self) -> String {
+    format!("{:?}", self)
+  }
 }
 
 // Expiration times (in seconds)
@@ -87,6 +106,10 @@ pub fn wait_delivery_time() -> u64 {
   24 * 7 * 3600
 } // 7 days for delivery
 
+pub fn review_time() -> u64 {
+  24 * 14 * 3600
+} // 14 days for reviews after delivery
+
 #[derive(Serialize, Deserialize, Clone, PartialEq, JsonSchema, Debug, Copy)]
 pub struct ExpirationTimes {
   #[serde(default = "courier_deposit_time")]
@@ -97,6 +120,8 @@ pub struct ExpirationTimes {
   pub for_wait_courier_in_department: u64,
   #[serde(default = "wait_delivery_time")]
   pub for_wait_delivery: u64,
+  #[serde(default = "review_time")]
+  pub for_review: u64,
 }
 
 impl Default for ExpirationTimes {
@@ -106,40 +131,373 @@ impl Default for ExpirationTimes {
       for_make_deposit: courier_deposit_time(),
       for_wait_delivery: wait_delivery_time(),
       for_set_details: set_details_time(),
+      for_review: review_time(),
     }
   }
 }
 
 #[rustfmt::skip]
 impl ExpirationTimes {
-  pub fn set_expiration_by_status(
-    &self,
-    deps: &mut DepsMut,
-    env: Env,
-  ) -> Result<bool, ContractError> {
-    let status = STATUS.load(deps.storage)?;
-    let block_time = env.block.time;
-
-    #[inline]
-    fn set_fixation(deps: &mut DepsMut, block_time: Timestamp, time: u64) -> Result<bool, ContractError> {
-      FIXATION_TIME.save(deps.storage, &block_time)?;
-      AVAILABLE_TIME.save(deps.storage, &time)?;
-      Ok(true)
+  pub fn set_expiration_by_status(&self, order: &mut Order, env: &Env) {
+    let time = match order.status {
+      Status::WaitCourierInDepartment => Some(self.for_wait_courier_in_department),
+      Status::WaitSenderDetails => Some(self.for_wait_courier_in_department),
+      Status::WaitDepositByCourier => Some(self.for_make_deposit),
+      Status::InProgress => Some(self.for_wait_delivery),
+      Status::Delivered => Some(self.for_review),
+      _ => None,
+    };
+
+    order.fixation_time = time.map(|_| env.block.time);
+    order.available_time = time;
+  }
+}
+
+// What happens with the platform fee when the order is canceled
+#[derive(
+  Serialize, Deserialize, Clone, PartialEq, JsonSchema, Debug, Default,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum FeeOnCancel {
+  // the payment is returned to the owner in full
+  #[default]
+  Refund,
+  // the fee is taken from the payment returned to the owner
+  Charge,
+}
+
+// Platform fee, taken from the order payment on delivery confirmation
+#[derive(Serialize, Deserialize, Clone, PartialEq, JsonSchema, Debug)]
+pub struct FeeConfig {
+  // fee in basis points of the order payment
+  pub bps: u16,
+  // account which receives fees
+  pub collector: Addr,
+  #[serde(default)]
+  pub on_cancel: FeeOnCancel,
+}
+
+impl FeeConfig {
+  pub fn fee_for(&self, payment: Uint128) -> Uint128 {
+    payment.multiply_ratio(self.bps, 10_000u128)
+  }
+}
+
+// Public key of the delivery confirmation coupon, hex encoded
+#[derive(Serialize, Deserialize, Clone, PartialEq, JsonSchema, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum ConfirmPublicKey {
+  // the coupon is a signature of sha256 of the coupon message
+  Secp256k1(String),
+  // the coupon is a signature of the coupon message itself
+  Ed25519(String),
+}
+
+// Message of the delivery confirmation coupon, the recipient signs its json
+// with the confirmation key, see `ConfirmPublicKey` for the exact bytes.
+// Chain id, contract address and order id bind the coupon to the order,
+// courier and nonce bind it to the current round of the order, so a coupon
+// leaked to the previous courier cannot be reused.
+#[derive(Serialize, Deserialize, Clone, PartialEq, JsonSchema, Debug)]
+pub struct CouponPayload {
+  pub chain_id: String,
+  pub contract_address: Addr,
+  pub order_id: u64,
+  pub courier: Addr,
+  pub nonce: u64,
+  // number of the parcel, only for batch orders
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub parcel: Option<u32>,
+}
+
+impl CouponPayload {
+  pub fn new(
+    env: &Env,
+    order_id: u64,
+    order: &Order,
+    courier: Addr,
+    parcel: u32,
+  ) -> Self {
+    Self {
+      chain_id: env.block.chain_id.clone(),
+      contract_address: env.contract.address.clone(),
+      order_id,
+      courier,
+      nonce: order.nonce,
+      parcel: order.is_batch().then_some(parcel),
     }
+  }
 
-    #[inline]
-    fn clear(deps: &mut DepsMut, ) -> Result<bool, ContractError> {
-      AVAILABLE_TIME.remove(deps.storage);
-      FIXATION_TIME.remove(deps.storage);
-      Ok(true)
+  // exact bytes of the message to sign
+  pub fn to_bytes(&self) -> StdResult<Vec<u8>> {
+    to_json_vec(self)
+  }
+
+  pub fn hash(&self) -> StdResult<Vec<u8>> {
+    Ok(Sha256::digest(self.to_bytes()?).to_vec())
+  }
+}
+
+// Cancellations of orders by the stage of the order
+#[derive(
+  Serialize, Deserialize, Clone, PartialEq, JsonSchema, Debug, Default,
+)]
+pub struct Cancellations {
+  pub wait_payment: u64,
+  pub wait_courier: u64,
+  pub wait_deposit: u64,
+  pub wait_details: u64,
+  pub wait_courier_in_department: u64,
+  pub in_progress: u64,
+}
+
+impl Cancellations {
+  pub fn add(&mut self, stage: &Status) {
+    let counter = match stage {
+      Status::WaitPaymentBySender => &mut self.wait_payment,
+      Status::WaitForCourier => &mut self.wait_courier,
+      Status::WaitDepositByCourier => &mut self.wait_deposit,
+      Status::WaitSenderDetails => &mut self.wait_details,
+      Status::WaitCourierInDepartment => &mut self.wait_courier_in_department,
+      Status::InProgress => &mut self.in_progress,
+      _ => return,
+    };
+
+    *counter += 1;
+  }
+}
+
+// Ratings of the account left by the other party of delivered orders
+#[derive(
+  Serialize, Deserialize, Clone, PartialEq, JsonSchema, Debug, Default,
+)]
+pub struct Ratings {
+  pub count: u64,
+  // sum of all ratings, the average is `total / count`
+  pub total: u64,
+}
+
+impl Ratings {
+  pub fn add(&mut self, rating: u8) {
+    self.count += 1;
+    self.total += rating as u64;
+  }
+}
+
+#[derive(
+  Serialize, Deserialize, Clone, PartialEq, JsonSchema, Debug, Default,
+)]
+pub struct RoleStats {
+  // delivered orders
+  pub completed: u64,
+  // orders failed by the fault of the account
+  pub failed: u64,
+  // orders canceled by the account
+  pub cancellations: Cancellations,
+  // sum of payments of the delivered orders
+  pub volume: Uint128,
+  #[serde(default)]
+  pub ratings: Ratings,
+}
+
+// Delivery history of the account, as a courier and as a sender
+#[derive(
+  Serialize, Deserialize, Clone, PartialEq, JsonSchema, Debug, Default,
+)]
+pub struct Reputation {
+  pub as_courier: RoleStats,
+  pub as_sender: RoleStats,
+}
+
+// Requirement of the order for the courier reputation
+#[derive(Serialize, Deserialize, Clone, PartialEq, JsonSchema, Debug)]
+pub struct ReputationRequirement {
+  // minimum of delivered orders as a courier
+  #[serde(default)]
+  pub min_completed: u64,
+  // maximum of failed orders as a courier
+  pub max_failed: Option<u64>,
+}
+
+impl ReputationRequirement {
+  pub fn is_met(&self, reputation: &Reputation) -> bool {
+    let stats = &reputation.as_courier;
+
+    stats.completed >= self.min_completed
+      && self.max_failed.is_none_or(|max| stats.failed <= max)
+  }
+}
+
+// Characters of the geohash grid, by the index of the cell
+pub const GEOHASH_ALPHABET: &str = "0123456789bcdefghjkmnpqrstuvwxyz";
+// 12 characters are a cell of a few centimeters, longer hashes make no sense
+pub const MAX_GEOHASH_LENGTH: usize = 12;
+
+// Point on the map, latitude and longitude in degrees
+#[derive(Serialize, Deserialize, Clone, PartialEq, JsonSchema, Debug)]
+pub struct Coordinates {
+  pub lat: SignedDecimal,
+  pub lng: SignedDecimal,
+}
+
+// Approximate area of the delivery point, visible to everyone
+#[derive(Serialize, Deserialize, Clone, PartialEq, JsonSchema, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum Area {
+  // rectangle between the south-west and the north-east corners
+  BoundingBox {
+    south_west: Coordinates,
+    north_east: Coordinates,
+  },
+  // cell of the geohash grid, a shorter hash is a larger cell
+  Geohash(String),
+}
+
+impl Coordinates {
+  // Cell of the geohash grid with the point, of the given length
+  pub fn geohash(&self, length: usize) -> String {
+    let degrees =
+      |value: i128| value * 10i128.pow(SignedDecimal::DECIMAL_PLACES);
+    let alphabet = GEOHASH_ALPHABET.as_bytes();
+
+    let point = (self.lng.atomics().i128(), self.lat.atomics().i128());
+    let mut lng_range = (degrees(-180), degrees(180));
+    let mut lat_range = (degrees(-90), degrees(90));
+
+    // bits of the hash are halves of the longitude and the latitude in turn
+    let mut is_lng = true;
+    let mut hash = String::with_capacity(length);
+
+    for _ in 0..length {
+      let mut index = 0;
+
+      for _ in 0..5 {
+        let (range, value) = match is_lng {
+          true => (&mut lng_range, point.0),
+          false => (&mut lat_range, point.1),
+        };
+
+        let middle = (range.0 + range.1) / 2;
+        index <<= 1;
+
+        if value >= middle {
+          index |= 1;
+          range.0 = middle;
+        } else {
+          range.1 = middle;
+        }
+
+        is_lng = !is_lng;
+      }
+
+      hash.push(alphabet[index] as char);
+    }
+
+    hash
+  }
+}
+
+impl Area {
+  // Smallest geohash cell which contains the whole area, empty if the area
+  // crosses the cells of the first level
+  pub fn geohash_cell(&self) -> String {
+    match self {
+      Area::Geohash(hash) => hash.clone(),
+      Area::BoundingBox {
+        south_west,
+        north_east,
+      } => {
+        let south_west = south_west.geohash(MAX_GEOHASH_LENGTH);
+        let north_east = north_east.geohash(MAX_GEOHASH_LENGTH);
+
+        south_west
+          .chars()
+          .zip(north_east.chars())
+          .take_while(|(a, b)| a == b)
+          .map(|(a, _)| a)
+          .collect()
+      }
     }
+  }
+}
+
+// Public key encryption schemes of the delivery details, the sender encrypts
+// with an ephemeral key and the courier public key
+#[derive(Serialize, Deserialize, Clone, PartialEq, JsonSchema, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum EncryptionAlgorithm {
+  // nacl box, 24 bytes nonce
+  X25519XSalsa20Poly1305,
+  // x25519 key exchange with chacha20-poly1305 (IETF), 12 bytes nonce
+  X25519ChaCha20Poly1305,
+}
+
+impl EncryptionAlgorithm {
+  pub fn public_key_length(&self) -> usize {
+    32
+  }
 
-    match status {
-      Status::WaitCourierInDepartment => set_fixation(deps, block_time, self.for_wait_courier_in_department),
-      Status::WaitSenderDetails => set_fixation(deps, block_time, self.for_wait_courier_in_department),
-      Status::WaitDepositByCourier => set_fixation(deps, block_time, self.for_make_deposit),
-      Status::InProgress => set_fixation(deps, block_time, self.for_wait_delivery),
-      _ => clear(deps),
+  pub fn nonce_length(&self) -> usize {
+    match self {
+      EncryptionAlgorithm::X25519XSalsa20Poly1305 => 24,
+      EncryptionAlgorithm::X25519ChaCha20Poly1305 => 12,
     }
   }
 }
+
+// Encryption public key of the courier, registered with the application
+#[derive(Serialize, Deserialize, Clone, PartialEq, JsonSchema, Debug)]
+pub struct EncryptionKey {
+  pub algorithm: EncryptionAlgorithm,
+  pub public_key: Binary,
+}
+
+// Delivery details encrypted for the courier: exact locations and the comment,
+// the format of the plaintext is up to the clients
+#[derive(Serialize, Deserialize, Clone, PartialEq, JsonSchema, Debug)]
+pub struct Envelope {
+  pub algorithm: EncryptionAlgorithm,
+  // public part of the one-time key of the sender
+  pub ephemeral_public_key: Binary,
+  pub nonce: Binary,
+  pub ciphertext: Binary,
+}
+
+// Terms of a relay leg, the parcel goes from the end of the previous leg to
+// the area of this leg, where the next courier or the recipient signs the
+// handoff coupon
+#[derive(Serialize, Deserialize, Clone, PartialEq, JsonSchema, Debug)]
+pub struct Leg {
+  pub to: Area,
+  // public key of the handoff coupon at the end of the leg
+  pub confirm_public_key: ConfirmPublicKey,
+  pub deposit_amount: Uint128,
+  // share of the order payment for the courier of the leg
+  pub payment_amount: Uint128,
+}
+
+// Additional parcel of the batch order, with its own destination and coupon
+#[derive(Serialize, Deserialize, Clone, PartialEq, JsonSchema, Debug)]
+pub struct Parcel {
+  pub to: Area,
+  pub confirm_public_key: ConfirmPublicKey,
+  // share of the order payment for the delivery of the parcel
+  pub payment_amount: Uint128,
+}
+
+// Stake of the bonded courier, collateral of the bonded orders instead of
+// the per-order deposit
+#[derive(
+  Serialize, Deserialize, Clone, PartialEq, JsonSchema, Debug, Default,
+)]
+pub struct Stake {
+  pub amount: Uint128,
+  // part of the stake locked as collateral of the orders in progress
+  pub locked: Uint128,
+}
+
+impl Stake {
+  pub fn free(&self) -> Uint128 {
+    self.amount - self.locked
+  }
+}
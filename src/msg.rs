@@ -1,32 +1,101 @@
 use cosmwasm_std::{Addr, DepsMut, MessageInfo, Uint128};
 use serde::{Deserialize, Serialize};
 use schemars::JsonSchema;
+use cw20::Cw20ReceiveMsg;
+use cw_asset::AssetInfo;
 
 use crate::error::ContractError;
+use crate::methods::{MAX_NEXT_LEGS, MAX_PARCELS};
 use crate::models::*;
 use crate::state::*;
 use crate::utils::*;
 
 #[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, PartialEq)]
 pub struct InstantiateLocationInfo {
-  pub from: String,
-  pub to: String,
+  pub from: Area,
+  pub to: Area,
 }
 
 #[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, PartialEq)]
-pub struct DetailsLocationInfo {
-  pub from: String,
-  pub to: String,
+pub struct InstantiateMsg {
+  // address of the contract cw20 tokens for payment
+  pub token_address: Option<Addr>,
+  // denom of the native coins for payment, if payment is not in cw20 tokens
+  pub native_denom: Option<String>,
+  // account which resolves disputes between the sender and the courier
+  pub arbiter: Option<Addr>,
+  // reward for settling of an expired order, taken from the order refunds
+  pub settle_bounty: Option<Uint128>,
+  // platform fee from the delivery payments
+  pub fee: Option<FeeConfig>,
+  // premium of the parcel insurance in basis points of the declared value,
+  // insurance is disabled without it
+  pub insurance_premium_bps: Option<u16>,
+}
+
+impl InstantiateMsg {
+  pub fn setup(&self, deps: &mut DepsMut) -> Result<(), ContractError> {
+    let asset = match (&self.token_address, &self.native_denom) {
+      (Some(token_address), None) => {
+        // check that the contract exists, and persistently save information about it in our contract
+        let token_info = get_token_info(deps, token_address.to_string())?;
+        TOKEN_INFO.save(deps.storage, &token_info)?;
+
+        AssetInfo::cw20(token_address.clone())
+      }
+
+      (None, Some(denom)) if !denom.is_empty() => {
+        AssetInfo::native(denom.clone())
+      }
+
+      _ => return Err(ContractError::InvalidPaymentAsset {}),
+    };
+
+    PAYMENT_ASSET.save(deps.storage, &asset)?;
+
+    SETTLE_BOUNTY
+      .save(deps.storage, &self.settle_bounty.unwrap_or_default())?;
+
+    if let Some(fee) = &self.fee {
+      if fee.bps > 10_000 {
+        return Err(ContractError::InvalidFee {});
+      }
+
+      let fee = FeeConfig {
+        collector: deps.api.addr_validate(fee.collector.as_str())?,
+        ..fee.clone()
+      };
+
+      FEE.save(deps.storage, &fee)?;
+    }
+
+    if let Some(premium_bps) = self.insurance_premium_bps {
+      if premium_bps > 10_000 {
+        return Err(ContractError::InvalidFee {});
+      }
+
+      INSURANCE_PREMIUM_BPS.save(deps.storage, &premium_bps)?;
+    }
+
+    INSURANCE_POOL.save(deps.storage, &Uint128::zero())?;
+
+    if let Some(arbiter) = &self.arbiter {
+      let arbiter = deps.api.addr_validate(arbiter.as_str())?;
+      ARBITER.save(deps.storage, &arbiter)?;
+    }
+
+    ORDER_COUNT.save(deps.storage, &0)?;
+
+    Ok(())
+  }
 }
 
 #[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, PartialEq)]
-pub struct InstantiateMsg {
+pub struct CreateOrderMsg {
   // approximate areas of delivery, from where and to
   pub location: InstantiateLocationInfo,
-  // address of the contract cw20 tokens for payment
-  pub token_address: Addr,
   // public key of the coupon for delivery verification
-  pub confirm_public_key: String,
+  pub confirm_public_key: ConfirmPublicKey,
 
   // required deposit from the courier
   pub deposit_amount: Uint128,
@@ -35,85 +104,412 @@ pub struct InstantiateMsg {
 
   // config for expiration times
   pub expiration_times: Option<ExpirationTimes>,
+  // minimal reputation of couriers who can apply for the order
+  pub courier_requirement: Option<ReputationRequirement>,
+  // relay legs after the first one, then `location.to` and the confirmation
+  // key are the first handoff point, and the payment is the first leg share
+  pub next_legs: Option<Vec<Leg>>,
+  // additional parcels of the batch order, then `location.to` and the
+  // confirmation key are of the first parcel, its share is the rest of the payment
+  pub parcels: Option<Vec<Parcel>>,
+  // value of the parcel to insure, the premium is paid with the payment and
+  // is not refunded if the order is canceled
+  pub declared_value: Option<Uint128>,
+  // only couriers with enough free stake can apply, the stake is locked
+  // instead of the deposit transfer
+  pub bonded: Option<bool>,
 }
 
-impl InstantiateMsg {
+impl CreateOrderMsg {
   pub fn setup(
     &self,
     deps: &mut DepsMut,
     info: &MessageInfo,
-  ) -> Result<(), ContractError> {
-    // check that the contract exists, and persistently save information about it in our contract
-    let token_info = get_token_info(deps, self.token_address.to_string())?;
-    TOKEN.save(deps.storage, &self.token_address)?;
-    TOKEN_INFO.save(deps.storage, &token_info)?;
+  ) -> Result<u64, ContractError> {
+    // verify & set public key
+    let confirm_public_key = check_and_serialize_public_key(
+      deps.api,
+      self.confirm_public_key.clone(),
+    )?;
 
-    // saving basic values
-    ROUGH_FROM_LOCATION.save(deps.storage, &self.location.from)?;
-    ROUGH_TO_LOCATION.save(deps.storage, &self.location.to)?;
+    let rough_from_location = check_area(self.location.from.clone())?;
+    let rough_to_location = check_area(self.location.to.clone())?;
 
-    DEPOSIT_AMOUNT.save(deps.storage, &self.deposit_amount)?;
-    PAYMENT_AMOUNT.save(deps.storage, &self.payment_amount)?;
+    let next_legs = self.next_legs.clone().unwrap_or_default();
 
-    STATUS.save(deps.storage, &Status::WaitPaymentBySender)?;
-    OWNER.save(deps.storage, &info.sender)?;
+    if next_legs.len() > MAX_NEXT_LEGS {
+      return Err(ContractError::TooManyLegs(MAX_NEXT_LEGS));
+    }
 
-    let expiration_times = self.expiration_times.unwrap_or_default();
-    EXPIRATION_TIMES.save(deps.storage, &expiration_times)?;
+    let next_legs = next_legs
+      .into_iter()
+      .map(|leg| {
+        Ok(Leg {
+          to: check_area(leg.to)?,
+          confirm_public_key: check_and_serialize_public_key(
+            deps.api,
+            leg.confirm_public_key,
+          )?,
+          ..leg
+        })
+      })
+      .collect::<Result<Vec<_>, ContractError>>()?;
 
-    // verify & set public key
-    let public_key =
-      check_and_serialize_public_key(self.confirm_public_key.clone())?;
+    let parcels = self.parcels.clone().unwrap_or_default();
 
-    CONFIRM_PUBLIC_KEY.save(deps.storage, &public_key)?;
+    if parcels.len() > MAX_PARCELS {
+      return Err(ContractError::InvalidParcels(format!(
+        "max number of additional parcels is {}",
+        MAX_PARCELS,
+      )));
+    }
 
-    Ok(())
+    if !parcels.is_empty() && !next_legs.is_empty() {
+      return Err(ContractError::InvalidParcels(
+        "relay orders cannot have several parcels".into(),
+      ));
+    }
+
+    let parcels = parcels
+      .into_iter()
+      .map(|parcel| {
+        Ok(Parcel {
+          to: check_area(parcel.to)?,
+          confirm_public_key: check_and_serialize_public_key(
+            deps.api,
+            parcel.confirm_public_key,
+          )?,
+          ..parcel
+        })
+      })
+      .collect::<Result<Vec<_>, ContractError>>()?;
+
+    let order = Order {
+      owner: info.sender.clone(),
+      courier: None,
+      status: Status::WaitPaymentBySender,
+
+      confirm_public_key,
+      nonce: 0,
+      legacy_coupon: false,
+      expiration_times: self.expiration_times.unwrap_or_default(),
+      fixation_time: None,
+      available_time: None,
+
+      courier_encryption_key: None,
+      details: None,
+
+      rough_from_location,
+      rough_to_location,
+      courier_requirement: self.courier_requirement.clone(),
+
+      leg: 0,
+      next_legs,
+
+      parcels,
+      delivered_parcels: vec![],
+      paid_payment: Uint128::zero(),
+      paid_deposit: Uint128::zero(),
+
+      declared_value: None,
+      insurance_premium: Uint128::zero(),
+
+      bonded: self.bonded.unwrap_or_default(),
+
+      parcel_issued: false,
+      parcel_picked_up: false,
+
+      deposit_amount: self.deposit_amount,
+      payment_amount: self.payment_amount,
+    };
+
+    check_parcels_payment(&order)?;
+
+    let order = match self.declared_value {
+      Some(declared_value) => {
+        let premium_bps = INSURANCE_PREMIUM_BPS
+          .may_load(deps.storage)?
+          .ok_or(ContractError::InsuranceDisabled {})?;
+
+        Order {
+          declared_value: Some(declared_value),
+          insurance_premium: declared_value
+            .multiply_ratio(premium_bps, 10_000u128),
+          ..order
+        }
+      }
+
+      None => order,
+    };
+
+    let order_id = ORDER_COUNT.load(deps.storage)? + 1;
+    orders().save(deps.storage, order_id, &order)?;
+    ORDER_COUNT.save(deps.storage, &order_id)?;
+
+    Ok(order_id)
   }
 }
 
+// Changes of the order terms before a courier is approved, empty fields stay the same
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, PartialEq)]
+pub struct UpdateOrderMsg {
+  pub order_id: u64,
+  pub location: Option<InstantiateLocationInfo>,
+
+  pub deposit_amount: Option<Uint128>,
+  // if the order is already paid, the difference is taken from the sender
+  // or returned to the sender
+  pub payment_amount: Option<Uint128>,
+
+  pub expiration_times: Option<ExpirationTimes>,
+}
+
 #[derive(Serialize, Deserialize, Clone, PartialEq, JsonSchema, Debug)]
 #[serde(rename_all = "snake_case")]
 pub enum ExecuteMsg {
+  // Create a new delivery order, the sender becomes the owner of the order
+  CreateOrder(CreateOrderMsg),
+  // Change the order terms while it is waiting for payment or for a courier
+  UpdateOrder(UpdateOrderMsg),
+  // Receive cw20 tokens sent with `ReceiveMsg` as a payment or a deposit
+  Receive(Cw20ReceiveMsg),
   // Make payment for shipping from the sender
-  MakePayForShipping {},
-  // The courier accepts the order
-  AcceptApplication {},
+  MakePayForShipping {
+    order_id: u64,
+  },
+  // The sender raises the payment while the order is waiting for a courier
+  TopUpPayment {
+    order_id: u64,
+    amount: Uint128,
+  },
+  // The courier applies for the order, the sender approves one of applications
+  AcceptApplication {
+    order_id: u64,
+    // price for the delivery asked by the courier, by default the order payment
+    price: Option<Uint128>,
+    message: Option<String>,
+    // key for encryption of the delivery details to the courier
+    encryption_key: EncryptionKey,
+  },
+  // The sender approves the courier from the applications
+  ApproveCourier {
+    order_id: u64,
+    courier: Addr,
+  },
+  // The sender rejects the courier application
+  RejectApplication {
+    order_id: u64,
+    courier: Addr,
+  },
   // Make a deposit for delivery from the courier
-  MakeDepositForShipping {},
-  // Update ticket comment with encrypted exact details
+  MakeDepositForShipping {
+    order_id: u64,
+  },
+  // The approved courier of the order migrated from 0.1.x registers the
+  // encryption key, orders created since then have it from the application
+  SetEncryptionKey {
+    order_id: u64,
+    encryption_key: EncryptionKey,
+  },
+  // Set exact locations and the comment, encrypted with the courier encryption key
   SetDetails {
-    location: DetailsLocationInfo,
-    comment: String,
+    order_id: u64,
+    details: Envelope,
   },
   // Cancel Delivery
   CancelDelivery {
+    order_id: u64,
     // @TODO: for example, add later the reasons for refusal
     // of delivery by the courier or sender
   },
-  // The parcel was given to the courier, delivery in progress
+  // Anyone can settle the order after expiration of the current stage,
+  // refunds are the same as if the owner cancels the order
+  Settle {
+    order_id: u64,
+  },
+  // Open a dispute about the delivery in progress, or about the parcel the
+  // sender gave but the courier did not confirm, funds are frozen until the
+  // arbiter decision
+  OpenDispute {
+    order_id: u64,
+    reason: String,
+  },
+  // The arbiter splits the payment and the deposit between the courier
+  // and the sender, the rest of funds is returned to the sender
+  ResolveDispute {
+    order_id: u64,
+    courier_payment: Uint128,
+    courier_deposit: Uint128,
+    reason: String,
+  },
+  // The sender gave the parcel to the courier, the delivery is in progress
+  // when the courier confirms the pickup too
   ParcelIssued {
+    order_id: u64,
     // @TODO: maybe add some details?
   },
+  // The courier picked up the parcel, the delivery is in progress when
+  // the sender confirms the handoff too
+  PickedUp {
+    order_id: u64,
+  },
   // The courier gave the parcel and received a coupon confirming
   // the signature to receive payment and deposit
   ConfirmDelivery {
+    order_id: u64,
     sign: String,
+    // number of the parcel of the batch order, by default the first one
+    parcel: Option<u32>,
+  },
+  // Stake funds as a bonded courier
+  Stake {
+    amount: Uint128,
+  },
+  // Withdraw the stake which is not locked in orders
+  Unstake {
+    amount: Uint128,
+  },
+  // Add funds to the insurance pool, anyone can do it
+  FundInsurancePool {
+    amount: Uint128,
+  },
+  // The sender sends a tip straight to the courier of the delivered order
+  Tip {
+    order_id: u64,
+    amount: Uint128,
+  },
+  // After delivery the owner and the courier rate each other once,
+  // until the review time of the order is over
+  LeaveReview {
+    order_id: u64,
+    // from 1 to 5
+    rating: u8,
+    review: Option<String>,
   },
 }
 
+impl ExecuteMsg {
+  // Messages which may carry native coins of the payment, coins sent with
+  // other messages are not counted anywhere and would be lost
+  pub fn is_payable(&self) -> bool {
+    matches!(
+      self,
+      ExecuteMsg::UpdateOrder(_)
+        | ExecuteMsg::MakePayForShipping { .. }
+        | ExecuteMsg::TopUpPayment { .. }
+        | ExecuteMsg::MakeDepositForShipping { .. }
+        | ExecuteMsg::Stake { .. }
+        | ExecuteMsg::FundInsurancePool { .. }
+        | ExecuteMsg::Tip { .. }
+    )
+  }
+}
+
+// Hook messages for cw20 `Send`, payment and deposit with a single transaction
+#[derive(Serialize, Deserialize, Clone, PartialEq, JsonSchema, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum ReceiveMsg {
+  // Payment for shipping from the sender
+  PayForShipping { order_id: u64 },
+  // Deposit for delivery from the courier
+  DepositForShipping { order_id: u64 },
+  // Raise of the payment from the sender, by the amount of sent tokens
+  TopUpPayment { order_id: u64 },
+  // Tip for the courier of the delivered order, sent tokens are forwarded
+  Tip { order_id: u64 },
+  // Funds for the insurance pool
+  FundInsurancePool {},
+  // Stake of the bonded courier
+  Stake {},
+}
+
 #[derive(Serialize, Deserialize, Clone, PartialEq, JsonSchema, Debug)]
 #[serde(rename_all = "snake_case")]
 pub enum QueryMsg {
+  // Get all information about the order
+  Order {
+    order_id: u64,
+  },
   // Get delivery status
-  Status {},
+  Status {
+    order_id: u64,
+  },
   // Get information about the token used in the contract
   TokenInfo {},
+  // Get the asset of payments and deposits, cw20 token or native coins
+  PaymentAsset {},
+  // Get the platform fee config, if any
+  Fee {},
+  // Get the balance and the premium of the insurance pool
+  InsurancePool {},
   // Get information about the courier
-  Courier {},
+  Courier {
+    order_id: u64,
+  },
   // Get information about funds
-  Funds {},
+  Funds {
+    order_id: u64,
+  },
   // Location Information
-  Locations {},
+  Locations {
+    order_id: u64,
+  },
+  // Get the expiration of the current stage and what each role can do now
+  Deadline {
+    order_id: u64,
+  },
+  // Get the message of the delivery coupon for the current courier of the order
+  CouponMessage {
+    order_id: u64,
+    // number of the parcel of the batch order, by default the first one
+    parcel: Option<u32>,
+  },
+  // Get the dispute of the order, if any
+  Dispute {
+    order_id: u64,
+  },
+  // Get the stake of the bonded courier
+  Stake {
+    address: Addr,
+  },
+  // Get the delivery history of the account
+  Reputation {
+    address: Addr,
+  },
+  // List courier applications for the order
+  Applications {
+    order_id: u64,
+    start_after: Option<Addr>,
+    limit: Option<u32>,
+  },
+  // List order ids, optionally only with the given status
+  Orders {
+    status: Option<Status>,
+    start_after: Option<u64>,
+    limit: Option<u32>,
+  },
+  // List ids of orders waiting for a courier which depart from the area,
+  // optionally with payment and deposit limits. A page checks a limited
+  // number of orders, continue after `last_scanned` of the response.
+  OpenOrders {
+    area: Area,
+    min_payment: Option<Uint128>,
+    max_deposit: Option<Uint128>,
+    start_after: Option<u64>,
+    limit: Option<u32>,
+  },
+  // List order ids created by the owner
+  OrdersByOwner {
+    owner: Addr,
+    start_after: Option<u64>,
+    limit: Option<u32>,
+  },
+  // List reviews about the account, by order id
+  Reviews {
+    address: Addr,
+    start_after: Option<u64>,
+    limit: Option<u32>,
+  },
 }
 
 #[derive(Serialize, Deserialize, JsonSchema)]
@@ -1,10 +1,29 @@
-use cosmwasm_std::{StdResult, Deps, Uint128, Addr};
+use cosmwasm_std::{
+  Order as SortOrder,
+  StdResult,
+  StdError,
+  Uint128,
+  Timestamp,
+  Binary,
+  Deps,
+  Addr,
+  Env,
+};
 use serde::{Deserialize, Serialize};
+use cw_storage_plus::Bound;
 use cw20::TokenInfoResponse;
+use cw_asset::AssetInfo;
 use schemars::JsonSchema;
 
 use crate::models::*;
 use crate::state::*;
+use crate::utils::*;
+
+// Settings for pagination
+const DEFAULT_LIMIT: u32 = 10;
+const MAX_LIMIT: u32 = 30;
+// Max number of orders checked by filters of a single page
+const MAX_SCAN_LIMIT: usize = 100;
 
 #[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, PartialEq)]
 pub struct FundsInfoResponse {
@@ -13,62 +32,477 @@ pub struct FundsInfoResponse {
 }
 
 #[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, PartialEq)]
-pub struct Direction {
-  pub from: String,
-  pub to: String,
+pub struct RoughDirection {
+  pub from: Area,
+  pub to: Area,
 }
 
 #[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, PartialEq)]
 pub struct LocationsResponse {
-  pub exact: Direction,
-  pub rough: Direction,
-  pub comment: String,
+  // exact locations and the comment, set by the sender after the courier
+  // deposit. Contract storage is public, only the courier can decrypt them.
+  pub details: Option<Envelope>,
+  pub rough: RoughDirection,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, PartialEq)]
+pub struct OrderResponse {
+  pub id: u64,
+  pub owner: Addr,
+  pub courier: Option<Addr>,
+  pub status: Status,
+
+  pub payment_amount: Uint128,
+  pub deposit_amount: Uint128,
+  // asset of the payment and the deposit
+  pub asset: AssetInfo,
+
+  pub locations: LocationsResponse,
+  // requirement for the reputation of couriers who apply for the order
+  pub courier_requirement: Option<ReputationRequirement>,
+
+  pub confirm_public_key: ConfirmPublicKey,
+  pub nonce: u64,
+  // coupons in the format of 0.1.x are accepted for the migrated order
+  pub legacy_coupon: bool,
+  // number of the current relay leg and terms of the next legs
+  pub leg: u32,
+  pub next_legs: Vec<Leg>,
+  // additional parcels of the batch order and numbers of delivered parcels
+  pub parcels: Vec<Parcel>,
+  pub delivered_parcels: Vec<u32>,
+  // parts of the payment and the deposit already paid for delivered parcels
+  pub paid_payment: Uint128,
+  pub paid_deposit: Uint128,
+
+  // insured value of the parcel and the premium paid into the pool
+  pub declared_value: Option<Uint128>,
+  pub insurance_premium: Uint128,
+  // the deposit is locked in the stake of the bonded courier
+  pub bonded: bool,
+
+  // confirmations of the parcel handoff by the owner and by the courier
+  pub parcel_issued: bool,
+  pub parcel_picked_up: bool,
+  // key for encryption of the details to the approved courier
+  pub courier_encryption_key: Option<EncryptionKey>,
+
+  pub expiration_times: ExpirationTimes,
+  pub deadline: DeadlineResponse,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, PartialEq)]
+pub struct CouponMessageResponse {
+  pub payload: CouponPayload,
+  // exact bytes of the message, signed with the ed25519 confirmation key
+  pub message: Binary,
+  // sha256 of the message, signed with the secp256k1 confirmation key
+  pub message_hash: Binary,
 }
 
-pub fn query_get_status(deps: Deps) -> StdResult<Status> {
-  STATUS.load(deps.storage)
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, PartialEq)]
+pub struct CancelTerms {
+  pub refund_receiver: RefundReceiver,
+  pub after_refund: AfterRefund,
+}
+
+impl From<(RefundReceiver, AfterRefund)> for CancelTerms {
+  fn from(
+    (refund_receiver, after_refund): (RefundReceiver, AfterRefund),
+  ) -> Self {
+    Self {
+      refund_receiver,
+      after_refund,
+    }
+  }
 }
 
-pub fn query_get_token_info(deps: Deps) -> StdResult<(Addr, TokenInfoResponse)> {
-  let token_address = TOKEN.load(deps.storage)?;
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, PartialEq)]
+pub struct DeadlineResponse {
+  // block time on which the current stage started
+  pub fixation_time: Option<Timestamp>,
+  // seconds available for the current stage
+  pub available_time: Option<u64>,
+  // block time after which the stage is expired
+  pub expires_at: Option<Timestamp>,
+  pub is_expired: bool,
+  // what happens if the owner cancels the order now, empty if not allowed
+  pub owner_can_cancel: Option<CancelTerms>,
+  // what happens if the courier cancels the order now, empty if not allowed
+  pub courier_can_cancel: Option<CancelTerms>,
+  // what happens if anyone settles the order now, empty if it cannot be settled
+  pub can_settle: Option<CancelTerms>,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, PartialEq)]
+pub struct ApplicationsResponse {
+  pub applications: Vec<Application>,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, PartialEq)]
+pub struct OrdersResponse {
+  pub orders: Vec<u64>,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, PartialEq)]
+pub struct OpenOrdersResponse {
+  pub orders: Vec<u64>,
+  // the last checked order, `start_after` of the next page. The page can
+  // be short if filters skip many orders, it is empty when nothing is left.
+  pub last_scanned: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, PartialEq)]
+pub struct InsurancePoolResponse {
+  pub balance: Uint128,
+  // premium in basis points of the declared value, empty if insurance is disabled
+  pub premium_bps: Option<u16>,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, PartialEq)]
+pub struct ReviewsResponse {
+  pub reviews: Vec<Review>,
+}
+
+pub fn query_get_status(deps: Deps, order_id: u64) -> StdResult<Status> {
+  let order = orders().load(deps.storage, order_id)?;
+  Ok(order.status)
+}
+
+pub fn query_get_token_info(
+  deps: Deps,
+) -> StdResult<(Addr, TokenInfoResponse)> {
+  let token_address = match PAYMENT_ASSET.load(deps.storage)? {
+    AssetInfo::Cw20(token_address) => token_address,
+    _ => return Err(StdError::generic_err("Payment is not in cw20 tokens")),
+  };
+
   let info = TOKEN_INFO.load(deps.storage)?;
 
   Ok((token_address, info))
 }
 
-pub fn query_get_courier(deps: Deps) -> StdResult<String> {
-  let courier = COURIER.may_load(deps.storage)?;
-  let addr = courier.and_then(|a| Some(a.to_string())).unwrap_or_default();
-  Ok(addr)
+pub fn query_get_payment_asset(deps: Deps) -> StdResult<AssetInfo> {
+  PAYMENT_ASSET.load(deps.storage)
 }
 
-pub fn query_get_funds(deps: Deps) -> StdResult<FundsInfoResponse> {
-  let deposit = DEPOSIT_AMOUNT.load(deps.storage)?;
-  let payment = PAYMENT_AMOUNT.load(deps.storage)?;
+pub fn query_get_fee(deps: Deps) -> StdResult<Option<FeeConfig>> {
+  FEE.may_load(deps.storage)
+}
 
-  Ok(FundsInfoResponse { deposit, payment })
+pub fn query_get_insurance_pool(
+  deps: Deps,
+) -> StdResult<InsurancePoolResponse> {
+  Ok(InsurancePoolResponse {
+    balance: INSURANCE_POOL.may_load(deps.storage)?.unwrap_or_default(),
+    premium_bps: INSURANCE_PREMIUM_BPS.may_load(deps.storage)?,
+  })
 }
 
-pub fn query_get_locations(deps: Deps) -> StdResult<LocationsResponse> {
-  let exact_from = EXACT_FROM_LOCATION.may_load(deps.storage)?;
-  let exact_to = EXACT_TO_LOCATION.may_load(deps.storage)?;
+pub fn query_get_courier(deps: Deps, order_id: u64) -> StdResult<Option<Addr>> {
+  let order = orders().load(deps.storage, order_id)?;
+  Ok(order.courier)
+}
+
+pub fn query_get_funds(
+  deps: Deps,
+  order_id: u64,
+) -> StdResult<FundsInfoResponse> {
+  let order = orders().load(deps.storage, order_id)?;
 
-  let rough_from = ROUGH_FROM_LOCATION.load(deps.storage)?;
-  let rough_to = ROUGH_TO_LOCATION.load(deps.storage)?;
+  Ok(FundsInfoResponse {
+    deposit: order.deposit_amount,
+    payment: order.payment_amount,
+  })
+}
 
-  let comment = COMMENT.may_load(deps.storage)?;
+pub fn query_get_locations(
+  deps: Deps,
+  order_id: u64,
+) -> StdResult<LocationsResponse> {
+  let order = orders().load(deps.storage, order_id)?;
+  Ok(get_locations(&order))
+}
 
-  Ok(LocationsResponse {
-    comment: comment.unwrap_or_default(),
+pub fn query_get_order(
+  deps: Deps,
+  env: Env,
+  order_id: u64,
+) -> StdResult<OrderResponse> {
+  let order = orders().load(deps.storage, order_id)?;
 
-    exact: Direction {
-      from: exact_from.unwrap_or_default(),
-      to: exact_to.unwrap_or_default(),
-    },
-    
-    rough: Direction {
-      from: rough_from,
-      to: rough_to,
+  Ok(OrderResponse {
+    id: order_id,
+    owner: order.owner.clone(),
+    courier: order.courier.clone(),
+    status: order.status.clone(),
+
+    payment_amount: order.payment_amount,
+    deposit_amount: order.deposit_amount,
+    asset: PAYMENT_ASSET.load(deps.storage)?,
+
+    locations: get_locations(&order),
+    courier_requirement: order.courier_requirement.clone(),
+
+    confirm_public_key: order.confirm_public_key.clone(),
+    nonce: order.nonce,
+    legacy_coupon: order.legacy_coupon,
+    leg: order.leg,
+    next_legs: order.next_legs.clone(),
+    parcels: order.parcels.clone(),
+    delivered_parcels: order.delivered_parcels.clone(),
+    paid_payment: order.paid_payment,
+    paid_deposit: order.paid_deposit,
+
+    declared_value: order.declared_value,
+    insurance_premium: order.insurance_premium,
+    bonded: order.bonded,
+
+    parcel_issued: order.parcel_issued,
+    parcel_picked_up: order.parcel_picked_up,
+    courier_encryption_key: order.courier_encryption_key.clone(),
+
+    expiration_times: order.expiration_times,
+    deadline: get_deadline(&order, &env),
+  })
+}
+
+fn get_locations(order: &Order) -> LocationsResponse {
+  LocationsResponse {
+    details: order.details.clone(),
+
+    rough: RoughDirection {
+      from: order.rough_from_location.clone(),
+      to: order.rough_to_location.clone(),
     },
+  }
+}
+
+pub fn query_get_deadline(
+  deps: Deps,
+  env: Env,
+  order_id: u64,
+) -> StdResult<DeadlineResponse> {
+  let order = orders().load(deps.storage, order_id)?;
+  Ok(get_deadline(&order, &env))
+}
+
+fn get_deadline(order: &Order, env: &Env) -> DeadlineResponse {
+  let expires_at = expiration_time(order);
+
+  // without the courier only the owner can cancel the order
+  let courier_can_cancel = match order.courier {
+    Some(_) => courier_can_cancel(order, env).map(CancelTerms::from),
+    None => None,
+  };
+
+  DeadlineResponse {
+    fixation_time: order.fixation_time,
+    available_time: order.available_time,
+    is_expired: expires_at.is_some_and(|time| env.block.time >= time),
+    expires_at,
+
+    owner_can_cancel: owner_can_cancel(order, env).map(CancelTerms::from),
+    courier_can_cancel,
+    can_settle: can_settle(order, env).map(CancelTerms::from),
+  }
+}
+
+pub fn query_get_coupon_message(
+  deps: Deps,
+  env: Env,
+  order_id: u64,
+  parcel: u32,
+) -> StdResult<CouponMessageResponse> {
+  let order = orders().load(deps.storage, order_id)?;
+
+  let courier = order
+    .courier
+    .clone()
+    .ok_or_else(|| StdError::generic_err("Courier not found"))?;
+
+  if order.parcel_terms(parcel).is_none() {
+    return Err(StdError::generic_err("Parcel not found"));
+  }
+
+  let payload = CouponPayload::new(&env, order_id, &order, courier, parcel);
+
+  Ok(CouponMessageResponse {
+    message: Binary::from(payload.to_bytes()?),
+    message_hash: Binary::from(payload.hash()?),
+    payload,
   })
 }
+
+pub fn query_get_dispute(
+  deps: Deps,
+  order_id: u64,
+) -> StdResult<Option<Dispute>> {
+  DISPUTES.may_load(deps.storage, order_id)
+}
+
+pub fn query_get_stake(deps: Deps, address: Addr) -> StdResult<Stake> {
+  let stake = STAKES.may_load(deps.storage, &address)?;
+  Ok(stake.unwrap_or_default())
+}
+
+pub fn query_get_reputation(
+  deps: Deps,
+  address: Addr,
+) -> StdResult<Reputation> {
+  let reputation = REPUTATION.may_load(deps.storage, &address)?;
+  Ok(reputation.unwrap_or_default())
+}
+
+pub fn query_get_applications(
+  deps: Deps,
+  order_id: u64,
+  start_after: Option<Addr>,
+  limit: Option<u32>,
+) -> StdResult<ApplicationsResponse> {
+  let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+  let start = start_after.as_ref().map(Bound::exclusive);
+
+  let applications = APPLICATIONS
+    .prefix(order_id)
+    .range(deps.storage, start, None, SortOrder::Ascending)
+    .take(limit)
+    .map(|item| item.map(|(_, application)| application))
+    .collect::<StdResult<Vec<_>>>()?;
+
+  Ok(ApplicationsResponse { applications })
+}
+
+pub fn query_get_orders(
+  deps: Deps,
+  status: Option<Status>,
+  start_after: Option<u64>,
+  limit: Option<u32>,
+) -> StdResult<OrdersResponse> {
+  let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+  let start = start_after.map(Bound::exclusive);
+
+  let orders = match status {
+    Some(status) => orders()
+      .idx
+      .status
+      .prefix(status.key())
+      .keys(deps.storage, start, None, SortOrder::Ascending)
+      .take(limit)
+      .collect::<StdResult<Vec<_>>>()?,
+
+    None => orders()
+      .keys(deps.storage, start, None, SortOrder::Ascending)
+      .take(limit)
+      .collect::<StdResult<Vec<_>>>()?,
+  };
+
+  Ok(OrdersResponse { orders })
+}
+
+pub fn query_get_open_orders(
+  deps: Deps,
+  area: Area,
+  min_payment: Option<Uint128>,
+  max_deposit: Option<Uint128>,
+  start_after: Option<u64>,
+  limit: Option<u32>,
+) -> StdResult<OpenOrdersResponse> {
+  let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+  let start = start_after.map(Bound::exclusive);
+
+  let cell = check_area(area)
+    .map_err(|err| StdError::generic_err(err.to_string()))?
+    .geohash_cell();
+
+  let is_suitable = |order: &Order| {
+    min_payment.is_none_or(|min| order.payment_amount >= min)
+      && max_deposit.is_none_or(|max| order.deposit_amount <= max)
+  };
+
+  // the area larger than the cells of the first level is the whole map
+  let candidates: Box<dyn Iterator<Item = StdResult<(u64, Order)>>> =
+    if cell.is_empty() {
+      Box::new(
+        orders()
+          .idx
+          .status
+          .prefix(Status::WaitForCourier.key())
+          .range(deps.storage, start, None, SortOrder::Ascending),
+      )
+    } else {
+      Box::new(
+        orders()
+          .idx
+          .open_area
+          .cells
+          .prefix(cell)
+          .keys(deps.storage, start, None, SortOrder::Ascending)
+          .map(|order_id| {
+            let order_id = order_id?;
+            Ok((order_id, orders().load(deps.storage, order_id)?))
+          }),
+      )
+    };
+
+  // the number of checked orders is limited, not only the number of found ones
+  let mut orders = vec![];
+  let mut last_scanned = None;
+
+  for item in candidates.take(MAX_SCAN_LIMIT) {
+    let (order_id, order) = item?;
+    last_scanned = Some(order_id);
+
+    if is_suitable(&order) {
+      orders.push(order_id);
+
+      if orders.len() == limit {
+        break;
+      }
+    }
+  }
+
+  Ok(OpenOrdersResponse {
+    orders,
+    last_scanned,
+  })
+}
+
+pub fn query_get_orders_by_owner(
+  deps: Deps,
+  owner: Addr,
+  start_after: Option<u64>,
+  limit: Option<u32>,
+) -> StdResult<OrdersResponse> {
+  let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+  let start = start_after.map(Bound::exclusive);
+
+  let orders = orders()
+    .idx
+    .owner
+    .prefix(owner)
+    .keys(deps.storage, start, None, SortOrder::Ascending)
+    .take(limit)
+    .collect::<StdResult<Vec<_>>>()?;
+
+  Ok(OrdersResponse { orders })
+}
+
+pub fn query_get_reviews(
+  deps: Deps,
+  address: Addr,
+  start_after: Option<u64>,
+  limit: Option<u32>,
+) -> StdResult<ReviewsResponse> {
+  let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+  let start = start_after.map(Bound::exclusive);
+
+  let reviews = REVIEWS
+    .prefix(&address)
+    .range(deps.storage, start, None, SortOrder::Ascending)
+    .take(limit)
+    .map(|item| item.map(|(_, review)| review))
+    .collect::<StdResult<Vec<_>>>()?;
+
+  Ok(ReviewsResponse { reviews })
+}
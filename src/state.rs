@@ -1,47 +1,372 @@
-use cosmwasm_std::{Addr, Uint128, Timestamp};
+use cosmwasm_std::{Addr, Uint128, Timestamp, StdResult, StdError, Storage, Empty};
 use cw20::TokenInfoResponse;
-use cw_storage_plus::Item;
+use cw_asset::AssetInfo;
+use cw_storage_plus::{IndexedMap, MultiIndex, IndexList, Index, Item, Map};
+use serde::{Deserialize, Serialize};
+use schemars::JsonSchema;
 use crate::models::*;
 
-// public key of the order confirmation coupon, according to which the courier receives his reward
-pub const CONFIRM_PUBLIC_KEY: Item<String> = Item::new("confirm_public_key");
-// config with preset of expiration times for cancel & refunds
-pub const EXPIRATION_TIMES: Item<ExpirationTimes> =
-  Item::new("expiration_times");
-// block time on which the new count was recorded
-pub const FIXATION_TIME: Item<Timestamp> = Item::new("fixation_time"); // Option<Timestamp>
-// time available for action (for example, for the delivery time after which the courier or sender can cancel the order)
-pub const AVAILABLE_TIME: Item<u64> = Item::new("available_time"); // Option<u64>
-
-// the exact coordinates of the delivery departure in geohash format encrypted with the courier's public key
-pub const EXACT_FROM_LOCATION: Item<String> =
-  Item::new("from_exact_location"); // Option<String>
-// exact coordinates of the delivery destination encrypted with the courier's public key in geohash format
-pub const EXACT_TO_LOCATION: Item<String> =
-  Item::new("to_exact_location"); // Option<String>
-
-// a comment from the sender encrypted with the courier's public key
-pub const COMMENT: Item<String> = Item::new("comment"); // Option<String>
-
-// approximate area of the place of departure of the parcel
-pub const ROUGH_FROM_LOCATION: Item<String> =
-  Item::new("from_rough_location");
-// approximate destination area of the parcel
-pub const ROUGH_TO_LOCATION: Item<String> =
-  Item::new("to_rough_location");
-
-// the amount of the required deposit from the courier, is set when creating the contract and does not change
-pub const DEPOSIT_AMOUNT: Item<Uint128> = Item::new("deposit_amount");
-// the amount of the delivery fee, is set when creating the contract and does not change
-pub const PAYMENT_AMOUNT: Item<Uint128> = Item::new("payment_amount");
-
-// courier account address accepted for delivery
-pub const COURIER: Item<Addr> = Item::new("courier"); // Option<Addr>
-// account address of the sender, the owner of the shipment
-pub const OWNER: Item<Addr> = Item::new("owner");
-// address of the cw20 contract, for manipulation and verification of tokens
-pub const TOKEN: Item<Addr> = Item::new("token");
-// information about the token, from the result of the first check
+#[derive(Serialize, Deserialize, Clone, PartialEq, JsonSchema, Debug)]
+pub struct Order {
+  // account address of the sender, the owner of the shipment
+  pub owner: Addr,
+  // courier account address accepted for delivery
+  pub courier: Option<Addr>,
+  // current order and delivery status
+  pub status: Status,
+
+  // public key of the order confirmation coupon, according to which the courier receives his reward
+  pub confirm_public_key: ConfirmPublicKey,
+  // number of the courier round, increased when the order starts over,
+  // is a part of the coupon message
+  pub nonce: u64,
+  // the order was migrated from 0.1.x with the courier approved, coupons
+  // signed over sha256 of the contract address are accepted until it starts over
+  #[serde(default)]
+  pub legacy_coupon: bool,
+  // config with preset of expiration times for cancel & refunds
+  pub expiration_times: ExpirationTimes,
+  // block time on which the new count was recorded
+  pub fixation_time: Option<Timestamp>,
+  // time available for action (for example, for the delivery time after which the courier or sender can cancel the order)
+  pub available_time: Option<u64>,
+
+  // encryption key of the approved courier, from the application
+  #[serde(default)]
+  pub courier_encryption_key: Option<EncryptionKey>,
+  // exact locations and the comment from the sender, encrypted with the courier encryption key
+  #[serde(default)]
+  pub details: Option<Envelope>,
+
+  // approximate area of the place of departure of the parcel
+  pub rough_from_location: Area,
+  // approximate destination area of the parcel
+  pub rough_to_location: Area,
+
+  // requirement for the reputation of couriers who apply for the order
+  #[serde(default)]
+  pub courier_requirement: Option<ReputationRequirement>,
+
+  // number of the current relay leg, from 0. Locations, the confirmation key
+  // and amounts of the order are the terms of the current leg.
+  #[serde(default)]
+  pub leg: u32,
+  // relay legs after the current one, empty for the direct delivery
+  #[serde(default)]
+  pub next_legs: Vec<Leg>,
+
+  // additional parcels of the batch order. The first parcel goes to the order
+  // destination with the order confirmation key, its share is the rest of the payment.
+  #[serde(default)]
+  pub parcels: Vec<Parcel>,
+  // numbers of delivered parcels, the first parcel is 0
+  #[serde(default)]
+  pub delivered_parcels: Vec<u32>,
+  // parts of the payment and the deposit already paid to the courier for delivered parcels
+  #[serde(default)]
+  pub paid_payment: Uint128,
+  #[serde(default)]
+  pub paid_deposit: Uint128,
+
+  // value of the parcel covered by the insurance pool, if insured
+  #[serde(default)]
+  pub declared_value: Option<Uint128>,
+  // premium paid into the insurance pool together with the payment
+  #[serde(default)]
+  pub insurance_premium: Uint128,
+
+  // only bonded couriers can apply, the deposit is locked in the courier
+  // stake instead of the transfer
+  #[serde(default)]
+  pub bonded: bool,
+
+  // the handoff of the parcel is confirmed by the owner and by the courier,
+  // the delivery starts when both confirmed it
+  #[serde(default)]
+  pub parcel_issued: bool,
+  #[serde(default)]
+  pub parcel_picked_up: bool,
+
+  // the amount of the required deposit from the courier, is set when creating the order and does not change
+  pub deposit_amount: Uint128,
+  // the amount of the delivery fee, is set when creating the order and does not change
+  pub payment_amount: Uint128,
+}
+
+impl Order {
+  // payment of the current leg and of the next relay legs
+  pub fn total_payment(&self) -> Uint128 {
+    self
+      .next_legs
+      .iter()
+      .fold(self.payment_amount, |total, leg| total + leg.payment_amount)
+  }
+
+  pub fn is_batch(&self) -> bool {
+    !self.parcels.is_empty()
+  }
+
+  pub fn parcels_count(&self) -> u32 {
+    self.parcels.len() as u32 + 1
+  }
+
+  // sum of shares of the additional parcels
+  pub fn parcels_payment(&self) -> Uint128 {
+    self
+      .parcels
+      .iter()
+      .map(|parcel| parcel.payment_amount)
+      .sum()
+  }
+
+  // confirmation key and the payment share of the parcel
+  pub fn parcel_terms(
+    &self,
+    parcel: u32,
+  ) -> Option<(&ConfirmPublicKey, Uint128)> {
+    match parcel {
+      0 => Some((
+        &self.confirm_public_key,
+        self.payment_amount - self.parcels_payment(),
+      )),
+      _ => self
+        .parcels
+        .get(parcel as usize - 1)
+        .map(|parcel| (&parcel.confirm_public_key, parcel.payment_amount)),
+    }
+  }
+
+  pub fn is_all_parcels_delivered(&self) -> bool {
+    self.delivered_parcels.len() as u32 == self.parcels_count()
+  }
+
+  // the insurance payout if the parcel is lost, the declared value of
+  // delivered parcels of the batch order is not covered any more
+  pub fn insured_value(&self) -> Uint128 {
+    let declared_value = self.declared_value.unwrap_or_default();
+
+    declared_value
+      .checked_multiply_ratio(
+        self.payment_amount - self.paid_payment,
+        self.payment_amount,
+      )
+      .unwrap_or(declared_value)
+  }
+
+  // part of the bonded courier stake locked for the order
+  pub fn collateral(&self) -> Uint128 {
+    match self.status {
+      Status::WaitSenderDetails
+      | Status::WaitCourierInDepartment
+      | Status::InProgress
+      | Status::Disputed
+        if self.bonded =>
+      {
+        self.deposit_amount - self.paid_deposit
+      }
+      _ => Uint128::zero(),
+    }
+  }
+
+  // funds of the order currently held by the contract
+  pub fn locked_amount(&self) -> Uint128 {
+    match self.status {
+      Status::WaitPaymentBySender => Uint128::zero(),
+      Status::WaitForCourier | Status::WaitDepositByCourier => {
+        self.total_payment()
+      }
+      Status::WaitSenderDetails
+      | Status::WaitCourierInDepartment
+      | Status::InProgress
+      | Status::Disputed => {
+        self.total_payment() + self.deposit_amount
+          - self.paid_payment
+          - self.paid_deposit
+      }
+      Status::Delivered
+      | Status::Failed
+      | Status::Closed
+      | Status::Resolved => Uint128::zero(),
+    }
+  }
+}
+
+pub struct OrderIndexes<'a> {
+  // orders by the sender address
+  pub owner: MultiIndex<'a, Addr, Order, u64>,
+  // orders by the current status, see `Status::key`
+  pub status: MultiIndex<'a, String, Order, u64>,
+  // orders waiting for a courier, by the departure area
+  pub open_area: AreaIndex<'a>,
+}
+
+impl<'a> IndexList<Order> for OrderIndexes<'a> {
+  fn get_indexes(
+    &'_ self,
+  ) -> Box<dyn Iterator<Item = &'_ dyn Index<Order>> + '_> {
+    let v: Vec<&dyn Index<Order>> =
+      vec![&self.owner, &self.status, &self.open_area];
+    Box::new(v.into_iter())
+  }
+}
+
+// Orders waiting for a courier by every prefix of the geohash cell of the
+// rough departure area, so a search in a cell finds orders in all of its
+// subcells. Orders with the empty cell are not indexed.
+pub struct AreaIndex<'a> {
+  pub cells: Map<'a, (String, u64), Empty>,
+}
+
+impl<'a> AreaIndex<'a> {
+  pub const fn new(namespace: &'a str) -> Self {
+    Self {
+      cells: Map::new(namespace),
+    }
+  }
+
+  fn prefixes(order: &Order) -> Vec<String> {
+    if order.status != Status::WaitForCourier {
+      return vec![];
+    }
+
+    let cell = order.rough_from_location.geohash_cell();
+    (1..=cell.len())
+      .map(|length| cell[..length].to_string())
+      .collect()
+  }
+
+  fn order_id(pk: &[u8]) -> StdResult<u64> {
+    let pk = pk
+      .try_into()
+      .map_err(|_| StdError::generic_err("Order id must be 8 bytes"))?;
+
+    Ok(u64::from_be_bytes(pk))
+  }
+}
+
+impl<'a> Index<Order> for AreaIndex<'a> {
+  fn save(
+    &self,
+    store: &mut dyn Storage,
+    pk: &[u8],
+    data: &Order,
+  ) -> StdResult<()> {
+    let order_id = Self::order_id(pk)?;
+
+    for prefix in Self::prefixes(data) {
+      self.cells.save(store, (prefix, order_id), &Empty {})?;
+    }
+
+    Ok(())
+  }
+
+  fn remove(
+    &self,
+    store: &mut dyn Storage,
+    pk: &[u8],
+    old_data: &Order,
+  ) -> StdResult<()> {
+    let order_id = Self::order_id(pk)?;
+
+    for prefix in Self::prefixes(old_data) {
+      self.cells.remove(store, (prefix, order_id));
+    }
+
+    Ok(())
+  }
+}
+
+// all orders of the contract, by order id
+pub fn orders<'a>() -> IndexedMap<'a, u64, Order, OrderIndexes<'a>> {
+  let indexes = OrderIndexes {
+    owner: MultiIndex::new(|o| o.owner.clone(), "orders", "orders__owner"),
+    status: MultiIndex::new(|o| o.status.key(), "orders", "orders__status"),
+    open_area: AreaIndex::new("orders__open_area"),
+  };
+
+  IndexedMap::new("orders", indexes)
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, JsonSchema, Debug)]
+pub struct Application {
+  // account address of the courier who applied for the order
+  pub courier: Addr,
+  // the price the courier asks for the delivery, must not exceed the order payment
+  pub price: Option<Uint128>,
+  // a message from the courier to the sender
+  pub message: Option<String>,
+  // key for encryption of the delivery details to the courier
+  #[serde(default)]
+  pub encryption_key: Option<EncryptionKey>,
+}
+
+// courier applications for orders waiting for a courier, by order id and courier address
+pub const APPLICATIONS: Map<(u64, &Addr), Application> =
+  Map::new("applications");
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, JsonSchema, Debug)]
+pub struct Ruling {
+  // part of the payment given to the courier
+  pub courier_payment: Uint128,
+  // part of the deposit returned to the courier
+  pub courier_deposit: Uint128,
+  // rest of the order funds returned to the owner
+  pub owner_amount: Uint128,
+  // explanation of the decision from the arbiter
+  pub reason: String,
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, JsonSchema, Debug)]
+pub struct Dispute {
+  // the owner or the courier who opened the dispute
+  pub opened_by: Addr,
+  // block time on which the dispute was opened
+  pub opened_at: Timestamp,
+  // what went wrong with the delivery
+  pub reason: String,
+  // decision of the arbiter, empty while the dispute is open
+  pub ruling: Option<Ruling>,
+}
+
+// disputes of the orders, by order id
+pub const DISPUTES: Map<u64, Dispute> = Map::new("disputes");
+
+// stakes of bonded couriers
+pub const STAKES: Map<&Addr, Stake> = Map::new("stakes");
+
+// reputation of accounts, updated when orders are delivered, failed or canceled
+pub const REPUTATION: Map<&Addr, Reputation> = Map::new("reputation");
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, JsonSchema, Debug)]
+pub struct Review {
+  pub order_id: u64,
+  // the owner or the courier of the order who left the review
+  pub author: Addr,
+  // from 1 to 5
+  pub rating: u8,
+  pub text: Option<String>,
+  pub created_at: Timestamp,
+}
+
+// reviews of delivered orders, by address of the reviewed party and order id
+pub const REVIEWS: Map<(&Addr, u64), Review> = Map::new("reviews");
+
+// id of the last created order
+pub const ORDER_COUNT: Item<u64> = Item::new("order_count");
+// id of the order for which the transfer submessage was sent, read back in the reply handlers
+pub const REPLY_ORDER_ID: Item<u64> = Item::new("reply_order_id");
+
+// account address which resolves disputes, disputes are disabled without it
+pub const ARBITER: Item<Addr> = Item::new("arbiter");
+// platform fee config, no fee is taken without it
+pub const FEE: Item<FeeConfig> = Item::new("fee");
+// premium of the insurance in basis points of the declared value, insurance is disabled without it
+pub const INSURANCE_PREMIUM_BPS: Item<u16> = Item::new("insurance_premium_bps");
+// funds of the insurance pool held by the contract, from premiums and donations
+pub const INSURANCE_POOL: Item<Uint128> = Item::new("insurance_pool");
+// reward for the account who settles an expired order
+pub const SETTLE_BOUNTY: Item<Uint128> = Item::new("settle_bounty");
+// asset of payments and deposits, cw20 token or native coins
+pub const PAYMENT_ASSET: Item<AssetInfo> = Item::new("payment_asset");
+// information about the cw20 token, from the result of the first check
 pub const TOKEN_INFO: Item<TokenInfoResponse> = Item::new("token_info");
-// current order and delivery status
-pub const STATUS: Item<Status> = Item::new("status");
@@ -1,21 +1,29 @@
 use cw20::{BalanceResponse, Cw20QueryMsg, TokenInfoResponse};
+use cw_asset::{AssetBase, AssetInfo};
 use sha2::{Digest, Sha256};
-use cw_asset::AssetBase;
 use hex::FromHex;
 
 use cosmwasm_std::{
+  Order as SortOrder,
+  to_json_binary,
   QueryRequest,
   MessageInfo,
   WasmQuery,
-  to_binary,
   StdResult,
+  Storage,
   DepsMut,
+  SignedDecimal,
+  Timestamp,
+  Decimal,
   Uint128,
   SubMsg,
+  Coin,
   Addr,
   Env,
+  Api,
 };
 
+use crate::methods::{MAX_CIPHERTEXT_LENGTH, MAX_MESSAGE_LENGTH};
 use crate::models::*;
 use crate::error::*;
 use crate::state::*;
@@ -24,7 +32,7 @@ pub fn get_token_info(
   deps: &DepsMut,
   contract_addr: String,
 ) -> StdResult<TokenInfoResponse> {
-  let msg = to_binary(&Cw20QueryMsg::TokenInfo {})?;
+  let msg = to_json_binary(&Cw20QueryMsg::TokenInfo {})?;
 
   let info: TokenInfoResponse = deps
     .querier
@@ -38,7 +46,7 @@ pub fn get_balance(
   token_contract_address: String,
   address: String,
 ) -> StdResult<Uint128> {
-  let msg = to_binary(&Cw20QueryMsg::Balance { address })?;
+  let msg = to_json_binary(&Cw20QueryMsg::Balance { address })?;
 
   let info: BalanceResponse =
     deps.querier.query(&QueryRequest::Wasm(WasmQuery::Smart {
@@ -49,93 +57,121 @@ pub fn get_balance(
   Ok(info.balance)
 }
 
-pub fn get_contract_balance(
+pub fn load_order(
+  storage: &dyn Storage,
+  order_id: u64,
+) -> Result<Order, ContractError> {
+  orders()
+    .may_load(storage, order_id)?
+    .ok_or(ContractError::OrderNotFound(order_id))
+}
+
+// Native coins are expected to be attached to the message, in this case there
+// is nothing to transfer and `None` is returned. For cw20 a `TransferFrom`
+// submessage is returned, the sender must give the allowance before.
+pub fn pay_to_contract_by_sender(
   deps: &DepsMut,
   env: &Env,
-) -> Result<Uint128, ContractError> {
-  let token_contract_address = TOKEN.load(deps.storage)?;
+  info: &MessageInfo,
 
-  get_balance(
-    deps,
-    token_contract_address.to_string(),
-    env.contract.address.to_string(),
-  )
-  .map_err(|error| ContractError::Std(error))
-}
+  amount: Uint128,
+  reply_id: u64,
+) -> Result<Option<SubMsg>, ContractError> {
+  let asset = PAYMENT_ASSET.load(deps.storage)?;
 
-pub fn get_payments_info(
-  deps: &DepsMut,
-) -> Result<(Uint128, Uint128), ContractError> {
-  let payment_amount = PAYMENT_AMOUNT.load(deps.storage)?;
-  let deposit_amount = DEPOSIT_AMOUNT.load(deps.storage)?;
+  if let AssetInfo::Native(denom) = &asset {
+    check_native_funds(info, denom, amount)?;
+    return Ok(None);
+  }
+
+  if !info.funds.is_empty() {
+    return Err(ContractError::UnexpectedFunds {});
+  }
+
+  let transfer_msg = AssetBase::new(asset, amount).transfer_from_msg(
+    info.sender.to_string(),
+    env.contract.address.to_string(),
+  )?;
 
-  Ok((payment_amount, deposit_amount))
+  Ok(Some(SubMsg::reply_on_success(transfer_msg, reply_id)))
 }
 
-pub fn pay_to_contract_by_sender(
+// Transfer from the sender straight to the receiver, native coins attached
+// to the message are forwarded by the contract
+pub fn pay_to_account_by_sender(
   deps: &DepsMut,
-  env: &Env,
   info: &MessageInfo,
+  receiver: Addr,
 
   amount: Uint128,
   reply_id: u64,
 ) -> Result<SubMsg, ContractError> {
-  let token_contract_address = TOKEN.load(deps.storage)?;
+  let asset = PAYMENT_ASSET.load(deps.storage)?;
+
+  if let AssetInfo::Native(denom) = &asset {
+    check_native_funds(info, denom, amount)?;
+    return pay_from_contract(deps, receiver, amount, reply_id);
+  }
+
+  if !info.funds.is_empty() {
+    return Err(ContractError::UnexpectedFunds {});
+  }
 
-  let transfer_msg = AssetBase::cw20(token_contract_address, amount)
-    .transfer_from_msg(
-      info.sender.to_string(),
-      env.contract.address.to_string(),
-    )?;
+  let transfer_msg = AssetBase::new(asset, amount)
+    .transfer_from_msg(info.sender.to_string(), receiver.to_string())?;
 
   Ok(SubMsg::reply_on_success(transfer_msg, reply_id))
 }
 
+pub fn check_native_funds(
+  info: &MessageInfo,
+  denom: &str,
+  amount: Uint128,
+) -> Result<(), ContractError> {
+  let expected = Coin::new(amount.u128(), denom);
+
+  match info.funds.as_slice() {
+    [coin] if *coin == expected => Ok(()),
+    [] if amount.is_zero() => Ok(()),
+    _ => Err(ContractError::InvalidFunds(expected)),
+  }
+}
+
 pub fn pay_from_contract(
   deps: &DepsMut,
   receiver: Addr,
   amount: Uint128,
   reply_id: u64,
 ) -> Result<SubMsg, ContractError> {
-  let token_contract_address = TOKEN.load(deps.storage)?;
-
-  let transfer_msg =
-    AssetBase::cw20(token_contract_address, amount).transfer_msg(receiver)?;
+  let asset = PAYMENT_ASSET.load(deps.storage)?;
+  let transfer_msg = AssetBase::new(asset, amount).transfer_msg(receiver)?;
 
   Ok(SubMsg::reply_on_success(transfer_msg, reply_id))
 }
 
-pub fn get_owner(deps: &DepsMut) -> Result<Addr, ContractError> {
-  OWNER
-    .load(deps.storage)
-    .map_err(|error| ContractError::Std(error))
-}
-
 pub fn check_is_owner(
-  deps: &DepsMut,
+  order: &Order,
   info: &MessageInfo,
 ) -> Result<Addr, ContractError> {
-  let owner = get_owner(deps)?;
-
-  if owner != info.sender {
+  if order.owner != info.sender {
     Err(ContractError::OwnerExpected {})
   } else {
-    Ok(owner)
+    Ok(order.owner.clone())
   }
 }
 
-pub fn get_courier(deps: &DepsMut) -> Result<Addr, ContractError> {
-  COURIER
-    .may_load(deps.storage)
-    .map_err(|error| ContractError::Std(error))
-    .and_then(|r| r.ok_or(ContractError::CourierNotApplyYet {}))
+pub fn get_courier(order: &Order) -> Result<Addr, ContractError> {
+  order
+    .courier
+    .clone()
+    .ok_or(ContractError::CourierNotApplyYet {})
 }
 
 pub fn check_is_courier(
-  deps: &DepsMut,
+  order: &Order,
   info: &MessageInfo,
 ) -> Result<Addr, ContractError> {
-  let courier = get_courier(deps)?;
+  let courier = get_courier(order)?;
 
   if info.sender != courier {
     Err(ContractError::CourierExpected {})
@@ -144,146 +180,455 @@ pub fn check_is_courier(
   }
 }
 
-pub fn is_time_over(deps: &DepsMut, env: &Env) -> Result<bool, ContractError> {
-  let fixation_time = FIXATION_TIME
-    .may_load(deps.storage)
-    .and_then(|t| Ok(t.unwrap_or_default()))?;
+pub fn update_reputation(
+  storage: &mut dyn Storage,
+  address: &Addr,
+  action: impl FnOnce(&mut Reputation),
+) -> StdResult<()> {
+  let mut reputation =
+    REPUTATION.may_load(storage, address)?.unwrap_or_default();
+  action(&mut reputation);
+  REPUTATION.save(storage, address, &reputation)
+}
+
+// The payment of the batch order must cover shares of the additional parcels
+pub fn check_parcels_payment(order: &Order) -> Result<(), ContractError> {
+  if order.payment_amount < order.parcels_payment() {
+    return Err(ContractError::InvalidParcels(format!(
+      "payment must be at least {}, the sum of the parcel shares",
+      order.parcels_payment(),
+    )));
+  }
+
+  Ok(())
+}
+
+pub fn clear_applications(
+  storage: &mut dyn Storage,
+  order_id: u64,
+) -> StdResult<()> {
+  let couriers = APPLICATIONS
+    .prefix(order_id)
+    .keys(storage, None, None, SortOrder::Ascending)
+    .collect::<StdResult<Vec<_>>>()?;
+
+  for courier in couriers {
+    APPLICATIONS.remove(storage, (order_id, &courier));
+  }
+
+  Ok(())
+}
+
+pub fn check_free_stake(
+  storage: &dyn Storage,
+  courier: &Addr,
+  amount: Uint128,
+) -> Result<(), ContractError> {
+  let stake = STAKES.may_load(storage, courier)?.unwrap_or_default();
+
+  if stake.free() < amount {
+    Err(ContractError::InsufficientStake(stake.free()))
+  } else {
+    Ok(())
+  }
+}
+
+// Unlock the collateral of the bonded order, the part which is not returned
+// to the courier is slashed, returns the slashed amount
+pub fn release_stake(
+  storage: &mut dyn Storage,
+  courier: &Addr,
+  collateral: Uint128,
+  returned: Uint128,
+) -> StdResult<Uint128> {
+  let mut stake = STAKES.may_load(storage, courier)?.unwrap_or_default();
+  let slashed = collateral - returned;
+
+  stake.locked -= collateral;
+  stake.amount -= slashed;
+
+  STAKES.save(storage, courier, &stake)?;
+
+  Ok(slashed)
+}
+
+pub fn check_message_length(message: &str) -> Result<(), ContractError> {
+  if message.len() > MAX_MESSAGE_LENGTH {
+    Err(ContractError::MessageTooLong(MAX_MESSAGE_LENGTH))
+  } else {
+    Ok(())
+  }
+}
 
-  let available_time = AVAILABLE_TIME
-    .may_load(deps.storage)
-    .and_then(|t| Ok(t.unwrap_or_default()))?;
+pub fn is_time_over(order: &Order, env: &Env) -> bool {
+  let fixation_time = order.fixation_time.unwrap_or_default();
+  let available_time = order.available_time.unwrap_or_default();
 
   let expiration_time = fixation_time.plus_seconds(available_time);
-  Ok(expiration_time > env.block.time)
+  env.block.time >= expiration_time
+}
+
+pub fn expiration_time(order: &Order) -> Option<Timestamp> {
+  let fixation_time = order.fixation_time?;
+  let available_time = order.available_time.unwrap_or_default();
+
+  Some(fixation_time.plus_seconds(available_time))
+}
+
+// Only stages with the expiration can be settled, and only after it,
+// refunds are the same as if the owner cancels the expired order
+pub fn can_settle(
+  order: &Order,
+  env: &Env,
+) -> Option<(RefundReceiver, AfterRefund)> {
+  if order.fixation_time.is_none() || !is_time_over(order, env) {
+    return None;
+  }
+
+  owner_can_cancel(order, env)
 }
 
 #[rustfmt::skip]
 pub fn owner_can_cancel(
-  deps: &DepsMut,
+  order: &Order,
   env: &Env,
-) -> Result<Option<(RefundReceiver, AfterRefund)>, ContractError> {
-  let status = STATUS.load(deps.storage)?;
-
+) -> Option<(RefundReceiver, AfterRefund)> {
   #[inline]
   fn check(
-    deps: &DepsMut,
+    order: &Order,
     env: &Env,
     receiver: RefundReceiver,
     action: AfterRefund,
-  ) -> Result<Option<(RefundReceiver, AfterRefund)>, ContractError> {
-    let can_cancel = is_time_over(deps, env)?;
-    Ok(can_cancel.then(|| (receiver, action)))
+  ) -> Option<(RefundReceiver, AfterRefund)> {
+    is_time_over(order, env).then_some((receiver, action))
   }
 
-  match status {
+  match order.status.clone() {
     // Refund both - payment and deposit, if expiration time is over,
     // because courier dont give parcel. Set status `Failed` for contract
     Status::WaitCourierInDepartment => {
-      check(deps, env, RefundReceiver::Both, AfterRefund::SetFailed)
+      check(order, env, RefundReceiver::Both, AfterRefund::SetFailed)
     }
     // Refund both - payment and deposit, if expiration time is over,
     // because courier dont give details of location or parcel. Set status `Failed` for contract
     Status::WaitSenderDetails => {
-      check(deps, env, RefundReceiver::Both, AfterRefund::SetFailed)
+      check(order, env, RefundReceiver::Both, AfterRefund::SetFailed)
     }
     // Refund owner payment, and close contract, if expiration time is over
     // (?) maybe add variation for cancel courier and start sratch courier over
     Status::WaitDepositByCourier => {
-      check(deps, env, RefundReceiver::Owner, AfterRefund::SetFailed)
+      check(order, env, RefundReceiver::Owner, AfterRefund::SetFailed)
     }
     // Refund payment and deposit to owner, if expiration time is over,
     // because his give parcel, and time for delivery is over.
     Status::InProgress => {
-      check(deps, env, RefundReceiver::Owner, AfterRefund::SetFailed)
+      check(order, env, RefundReceiver::Owner, AfterRefund::SetFailed)
     }
 
     other_case => {
       // if owner only create contract or make payment but dont find courier
       // he can close contract and give all funds
-      Ok(
-        (other_case == Status::WaitPaymentBySender
-          || other_case == Status::WaitForCourier)
-          .then(|| (RefundReceiver::Owner, AfterRefund::SetClosed)),
-      )
+      (other_case == Status::WaitPaymentBySender
+        || other_case == Status::WaitForCourier)
+        .then_some((RefundReceiver::Owner, AfterRefund::SetClosed))
     }
   }
 }
 
 #[rustfmt::skip]
 pub fn courier_can_cancel(
-  deps: &DepsMut,
+  order: &Order,
   env: &Env,
-) -> Result<Option<(RefundReceiver, AfterRefund)>, ContractError>
+) -> Option<(RefundReceiver, AfterRefund)>
 {
-  let status = STATUS.load(deps.storage)?;
-
   #[inline]
   fn check(
-    deps: &DepsMut,
+    order: &Order,
     env: &Env,
     receiver: RefundReceiver,
     action: AfterRefund,
-  ) -> Result<Option<(RefundReceiver, AfterRefund)>, ContractError>
+  ) -> Option<(RefundReceiver, AfterRefund)>
   {
-    let can_cancel = is_time_over(deps, env)?;
-    Ok(can_cancel.then(|| (receiver, action)))
+    is_time_over(order, env).then_some((receiver, action))
   }
 
-  match status {
+  match order.status.clone() {
     // Refund for courier deposit, because courier dont give parcel. After refund start find courier over.
-    // With this status = courier can cancel without wait end of expiration
+    // With this status = courier can cancel without wait end of expiration,
+    // but not once either side confirmed the handoff of the parcel, then the
+    // owner cancels after expiration or opens a dispute
     Status::WaitCourierInDepartment => {
-      Ok(Some((RefundReceiver::Courier, AfterRefund::StartOver)))
+      let is_handed_off = order.parcel_issued || order.parcel_picked_up;
+
+      (!is_handed_off)
+        .then_some((RefundReceiver::Courier, AfterRefund::StartOver))
     }
     // Refund courier deposit, if expiration time is over, 
     // because courier dont give details of location or parcel. 
     Status::WaitSenderDetails => {
-      check(deps, env, RefundReceiver::Courier, AfterRefund::StartOver)
+      check(order, env, RefundReceiver::Courier, AfterRefund::StartOver)
     }
     // No-one give refund, after that start find courier over.
     // With this status = courier can cancel without wait end of expiration
     Status::WaitDepositByCourier => {
-      Ok(Some((RefundReceiver::NoOne, AfterRefund::StartOver)))
+      Some((RefundReceiver::NoOne, AfterRefund::StartOver))
     }
     // Refund payment and deposit to owner, because his give parcel, and time for delivery is over.
     Status::InProgress => {
-      check(deps, env, RefundReceiver::Owner, AfterRefund::SetFailed)
+      check(order, env, RefundReceiver::Owner, AfterRefund::SetFailed)
     }
     // With other status courier not applied, and only owner can cancel request
-    _ => Ok(None),
+    _ => None,
+  }
+}
+
+// Verify the area and return it with the lowercase geohash
+pub fn check_area(area: Area) -> Result<Area, ContractError> {
+  #[inline]
+  fn invalid(reason: String) -> ContractError {
+    ContractError::InvalidArea(reason)
+  }
+
+  #[inline]
+  fn check_point(point: &Coordinates) -> Result<(), ContractError> {
+    // distance from the equator and from the prime meridian
+    let lat = point.lat.abs_diff(SignedDecimal::zero());
+    let lng = point.lng.abs_diff(SignedDecimal::zero());
+
+    if lat > Decimal::from_ratio(90u128, 1u128) {
+      return Err(invalid(format!("latitude {} is out of range", point.lat)));
+    }
+
+    if lng > Decimal::from_ratio(180u128, 1u128) {
+      return Err(invalid(format!("longitude {} is out of range", point.lng)));
+    }
+
+    Ok(())
+  }
+
+  match area {
+    Area::BoundingBox {
+      south_west,
+      north_east,
+    } => {
+      check_point(&south_west)?;
+      check_point(&north_east)?;
+
+      if south_west.lat > north_east.lat || south_west.lng > north_east.lng {
+        return Err(invalid(
+          "south-west corner must be below and left of north-east corner"
+            .into(),
+        ));
+      }
+
+      Ok(Area::BoundingBox {
+        south_west,
+        north_east,
+      })
+    }
+
+    Area::Geohash(hash) => {
+      let hash = hash.trim().to_lowercase();
+
+      if hash.is_empty() || hash.len() > MAX_GEOHASH_LENGTH {
+        return Err(invalid(format!(
+          "geohash must be from 1 to {} characters",
+          MAX_GEOHASH_LENGTH,
+        )));
+      }
+
+      if let Some(char) = hash.chars().find(|c| !GEOHASH_ALPHABET.contains(*c))
+      {
+        return Err(invalid(format!(
+          "unexpected geohash character {:?}",
+          char
+        )));
+      }
+
+      Ok(Area::Geohash(hash))
+    }
+  }
+}
+
+pub fn check_encryption_key(key: &EncryptionKey) -> Result<(), ContractError> {
+  let expected = key.algorithm.public_key_length();
+
+  if key.public_key.len() != expected {
+    return Err(ContractError::InvalidEncryption(format!(
+      "public key must be {} bytes, got {}",
+      expected,
+      key.public_key.len(),
+    )));
+  }
+
+  Ok(())
+}
+
+// The envelope must be encrypted with the algorithm of the courier key, sizes
+// of its parts are checked, the ciphertext itself cannot be checked
+pub fn check_envelope(
+  envelope: &Envelope,
+  courier_key: &EncryptionKey,
+) -> Result<(), ContractError> {
+  #[inline]
+  fn invalid(reason: String) -> ContractError {
+    ContractError::InvalidEncryption(reason)
+  }
+
+  let algorithm = &envelope.algorithm;
+
+  if *algorithm != courier_key.algorithm {
+    return Err(invalid(format!(
+      "the courier key is for {:?}",
+      courier_key.algorithm,
+    )));
+  }
+
+  if envelope.ephemeral_public_key.len() != algorithm.public_key_length() {
+    return Err(invalid(format!(
+      "ephemeral public key must be {} bytes",
+      algorithm.public_key_length(),
+    )));
+  }
+
+  if envelope.nonce.len() != algorithm.nonce_length() {
+    return Err(invalid(format!(
+      "nonce must be {} bytes",
+      algorithm.nonce_length(),
+    )));
   }
+
+  if envelope.ciphertext.is_empty()
+    || envelope.ciphertext.len() > MAX_CIPHERTEXT_LENGTH
+  {
+    return Err(invalid(format!(
+      "ciphertext must be from 1 to {} bytes",
+      MAX_CIPHERTEXT_LENGTH,
+    )));
+  }
+
+  Ok(())
 }
 
+// Verify the confirmation key and return it in the canonical lowercase hex
 pub fn check_and_serialize_public_key(
-  source: String,
-) -> Result<String, ContractError> {
-  // @TODO: need complex verify
-  <Vec<u8>>::from_hex(&*source).or(Err(ContractError::InvalidPublicKey))?;
+  api: &dyn Api,
+  source: ConfirmPublicKey,
+) -> Result<ConfirmPublicKey, ContractError> {
+  #[inline]
+  fn invalid(reason: String) -> ContractError {
+    ContractError::InvalidPublicKeyFormat(reason)
+  }
+
+  match source {
+    ConfirmPublicKey::Secp256k1(key) => {
+      let bytes = <Vec<u8>>::from_hex(key.trim())
+        .or(Err(invalid("secp256k1 key is not a hex string".into())))?;
+
+      match (bytes.first(), bytes.len()) {
+        (Some(0x02) | Some(0x03), 33) | (Some(0x04), 65) => {}
+
+        (Some(prefix @ 0x02..=0x04), length) => {
+          return Err(invalid(format!(
+            "secp256k1 key with prefix {:#04x} must not be {} bytes",
+            prefix, length,
+          )))
+        }
+
+        (Some(prefix), _) => {
+          return Err(invalid(format!(
+            "unexpected secp256k1 key prefix {:#04x}",
+            prefix,
+          )))
+        }
+
+        (None, _) => return Err(invalid("secp256k1 key is empty".into())),
+      }
+
+      // the key is parsed before the signature check, so a well-formed
+      // dummy signature fails only if the key is not a point on the curve
+      api
+        .secp256k1_verify(&[0; 32], &[1; 64], &bytes)
+        .or(Err(invalid("secp256k1 key is not on the curve".into())))?;
+
+      Ok(ConfirmPublicKey::Secp256k1(hex::encode(bytes)))
+    }
+
+    ConfirmPublicKey::Ed25519(key) => {
+      let bytes = <Vec<u8>>::from_hex(key.trim())
+        .or(Err(invalid("ed25519 key is not a hex string".into())))?;
+
+      if bytes.len() != 32 {
+        return Err(invalid(format!(
+          "ed25519 key must be 32 bytes, got {}",
+          bytes.len(),
+        )));
+      }
 
-  Ok(source)
+      Ok(ConfirmPublicKey::Ed25519(hex::encode(bytes)))
+    }
+  }
 }
 
 pub fn check_courier_signature(
   deps: &DepsMut,
-  contract_address: String,
+  coupon: &CouponPayload,
+  raw_signature: String,
+  confirm_key: &ConfirmPublicKey,
+) -> Result<(), ContractError> {
+  let signature = <Vec<u8>>::from_hex(&*raw_signature)
+    .or(Err(ContractError::InvalidSignature))?;
+
+  let is_valid = match confirm_key {
+    ConfirmPublicKey::Secp256k1(key) => {
+      let public_key =
+        <Vec<u8>>::from_hex(key).or(Err(ContractError::InvalidPublicKey))?;
+
+      deps
+        .api
+        .secp256k1_verify(&coupon.hash()?, &signature, &public_key)
+    }
+
+    ConfirmPublicKey::Ed25519(key) => {
+      let public_key =
+        <Vec<u8>>::from_hex(key).or(Err(ContractError::InvalidPublicKey))?;
+
+      deps
+        .api
+        .ed25519_verify(&coupon.to_bytes()?, &signature, &public_key)
+    }
+  };
+
+  match is_valid {
+    Ok(true) => Ok(()),
+    _ => Err(ContractError::InvalidSignature),
+  }
+}
+
+// Coupons of 0.1.x are secp256k1 signatures of sha256 of the contract address
+pub fn check_legacy_courier_signature(
+  deps: &DepsMut,
+  contract_address: &Addr,
   raw_signature: String,
-  confirm_key: String,
+  confirm_key: &ConfirmPublicKey,
 ) -> Result<(), ContractError> {
-  let mut message_digest = Sha256::new();
-  message_digest.update(&*contract_address);
+  let ConfirmPublicKey::Secp256k1(key) = confirm_key else {
+    return Err(ContractError::InvalidSignature);
+  };
 
-  let message_hash = message_digest.finalize();
+  let message_hash = Sha256::digest(contract_address.as_bytes());
 
   let signature = <Vec<u8>>::from_hex(&*raw_signature)
     .or(Err(ContractError::InvalidSignature))?;
 
-  let public_key = <Vec<u8>>::from_hex(&*confirm_key)
-    .or(Err(ContractError::InvalidPublicKey))?;
+  let public_key =
+    <Vec<u8>>::from_hex(key).or(Err(ContractError::InvalidPublicKey))?;
 
-  deps
+  match deps
     .api
-    .secp256k1_verify(&message_hash[..], &signature, &public_key)
-    .or(Err(ContractError::InvalidSignature))?;
-
-  Ok(())
+    .secp256k1_verify(&message_hash, &signature, &public_key)
+  {
+    Ok(true) => Ok(()),
+    _ => Err(ContractError::InvalidSignature),
+  }
 }
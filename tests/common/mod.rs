@@ -0,0 +1,620 @@
+#![allow(dead_code)]
+
+use cw_multi_test::{App, AppResponse, ContractWrapper, Executor};
+use k256::ecdsa::signature::hazmat::PrehashSigner;
+use k256::ecdsa::{Signature, SigningKey};
+use serde::{Deserialize, Serialize};
+use anyhow::Result as AnyResult;
+use cw_storage_plus::Map;
+
+use cw20::{
+  TokenInfoResponse,
+  Cw20ReceiveMsg,
+  Cw20ExecuteMsg,
+  BalanceResponse,
+  Cw20QueryMsg,
+};
+
+use cosmwasm_std::{
+  to_json_binary,
+  MessageInfo,
+  StdResult,
+  StdError,
+  Response,
+  Uint128,
+  DepsMut,
+  Binary,
+  Coin,
+  Deps,
+  Addr,
+  Env,
+};
+
+use figaro_contract::contract::{execute, instantiate, migrate, query, reply};
+use figaro_contract::*;
+
+pub const DENOM: &str = "ustake";
+pub const INITIAL_BALANCE: u128 = 1_000_000;
+
+pub const PAYMENT: u128 = 100;
+pub const DEPOSIT: u128 = 50;
+
+pub const OWNER: &str = "owner";
+pub const COURIER: &str = "courier";
+pub const OTHER_COURIER: &str = "other_courier";
+pub const ARBITER: &str = "arbiter";
+pub const KEEPER: &str = "keeper";
+pub const COLLECTOR: &str = "collector";
+
+// Secret key of the recipient, who signs the delivery coupon
+const RECIPIENT_SECRET: [u8; 32] = [7; 32];
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Asset {
+  Native,
+  Cw20,
+}
+
+pub const ASSETS: [Asset; 2] = [Asset::Native, Asset::Cw20];
+
+// Minimal cw20 token, allowances are not checked
+mod token {
+  use super::*;
+
+  const BALANCES: Map<&Addr, Uint128> = Map::new("balance");
+
+  #[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+  pub struct InstantiateMsg {
+    pub balances: Vec<(String, Uint128)>,
+  }
+
+  fn transfer(
+    deps: DepsMut,
+    from: &Addr,
+    to: &Addr,
+    amount: Uint128,
+  ) -> StdResult<()> {
+    if amount.is_zero() {
+      return Err(StdError::generic_err("Invalid zero amount"));
+    }
+
+    BALANCES.update(deps.storage, from, |balance| -> StdResult<_> {
+      Ok(balance.unwrap_or_default().checked_sub(amount)?)
+    })?;
+
+    BALANCES.update(deps.storage, to, |balance| -> StdResult<_> {
+      Ok(balance.unwrap_or_default() + amount)
+    })?;
+
+    Ok(())
+  }
+
+  pub fn instantiate(
+    deps: DepsMut,
+    _env: Env,
+    _info: MessageInfo,
+    msg: InstantiateMsg,
+  ) -> StdResult<Response> {
+    for (address, amount) in msg.balances {
+      BALANCES.save(deps.storage, &Addr::unchecked(address), &amount)?;
+    }
+
+    Ok(Response::new())
+  }
+
+  pub fn execute(
+    deps: DepsMut,
+    _env: Env,
+    info: MessageInfo,
+    msg: Cw20ExecuteMsg,
+  ) -> StdResult<Response> {
+    match msg {
+      Cw20ExecuteMsg::Transfer { recipient, amount } => {
+        transfer(deps, &info.sender, &Addr::unchecked(recipient), amount)?;
+        Ok(Response::new())
+      }
+
+      Cw20ExecuteMsg::TransferFrom {
+        owner,
+        recipient,
+        amount,
+      } => {
+        let owner = Addr::unchecked(owner);
+        transfer(deps, &owner, &Addr::unchecked(recipient), amount)?;
+        Ok(Response::new())
+      }
+
+      Cw20ExecuteMsg::Send {
+        contract,
+        amount,
+        msg,
+      } => {
+        let contract = Addr::unchecked(contract);
+        transfer(deps, &info.sender, &contract, amount)?;
+
+        let receive_msg = Cw20ReceiveMsg {
+          sender: info.sender.to_string(),
+          amount,
+          msg,
+        };
+
+        Ok(Response::new().add_message(receive_msg.into_cosmos_msg(contract)?))
+      }
+
+      _ => Err(StdError::generic_err("Not supported by the mock token")),
+    }
+  }
+
+  pub fn query(deps: Deps, _env: Env, msg: Cw20QueryMsg) -> StdResult<Binary> {
+    match msg {
+      Cw20QueryMsg::Balance { address } => {
+        let balance = BALANCES
+          .may_load(deps.storage, &Addr::unchecked(address))?
+          .unwrap_or_default();
+
+        to_json_binary(&BalanceResponse { balance })
+      }
+
+      Cw20QueryMsg::TokenInfo {} => to_json_binary(&TokenInfoResponse {
+        name: "Figaro Token".into(),
+        symbol: "FGR".into(),
+        decimals: 6,
+        total_supply: Uint128::zero(),
+      }),
+
+      _ => Err(StdError::generic_err("Not supported by the mock token")),
+    }
+  }
+}
+
+pub struct Suite {
+  pub app: App,
+  pub asset: Asset,
+  pub contract: Addr,
+  pub token: Option<Addr>,
+}
+
+impl Suite {
+  pub fn new(asset: Asset) -> Self {
+    Self::with_config(asset, |_| {})
+  }
+
+  pub fn with_config(
+    asset: Asset,
+    config: impl FnOnce(&mut InstantiateMsg),
+  ) -> Self {
+    let accounts = [OWNER, COURIER, OTHER_COURIER, KEEPER];
+
+    let mut app = App::new(|router, _, storage| {
+      for account in accounts {
+        router
+          .bank
+          .init_balance(
+            storage,
+            &Addr::unchecked(account),
+            vec![Coin::new(INITIAL_BALANCE, DENOM)],
+          )
+          .unwrap();
+      }
+    });
+
+    let token = match asset {
+      Asset::Native => None,
+      Asset::Cw20 => {
+        let code_id = app.store_code(Box::new(ContractWrapper::new(
+          token::execute,
+          token::instantiate,
+          token::query,
+        )));
+
+        let balances = accounts
+          .iter()
+          .map(|account| (account.to_string(), Uint128::new(INITIAL_BALANCE)))
+          .collect();
+
+        Some(
+          app
+            .instantiate_contract(
+              code_id,
+              Addr::unchecked(OWNER),
+              &token::InstantiateMsg { balances },
+              &[],
+              "token",
+              None,
+            )
+            .unwrap(),
+        )
+      }
+    };
+
+    let code_id = app.store_code(Box::new(
+      ContractWrapper::new(execute, instantiate, query)
+        .with_reply(reply)
+        .with_migrate(migrate),
+    ));
+
+    let mut msg = InstantiateMsg {
+      token_address: token.clone(),
+      native_denom: (asset == Asset::Native).then(|| DENOM.to_string()),
+      arbiter: Some(Addr::unchecked(ARBITER)),
+      settle_bounty: None,
+      fee: None,
+      insurance_premium_bps: None,
+    };
+
+    config(&mut msg);
+
+    let contract = app
+      .instantiate_contract(
+        code_id,
+        Addr::unchecked(OWNER),
+        &msg,
+        &[],
+        "figaro",
+        Some(OWNER.to_string()),
+      )
+      .unwrap();
+
+    Self {
+      app,
+      asset,
+      contract,
+      token,
+    }
+  }
+
+  pub fn balance(&self, account: &str) -> u128 {
+    match &self.token {
+      None => self
+        .app
+        .wrap()
+        .query_balance(account, DENOM)
+        .unwrap()
+        .amount
+        .u128(),
+
+      Some(token) => {
+        let response: BalanceResponse = self
+          .app
+          .wrap()
+          .query_wasm_smart(
+            token,
+            &Cw20QueryMsg::Balance {
+              address: account.to_string(),
+            },
+          )
+          .unwrap();
+
+        response.balance.u128()
+      }
+    }
+  }
+
+  // Balance of the account relative to the initial one
+  pub fn balance_change(&self, account: &str) -> i128 {
+    self.balance(account) as i128 - INITIAL_BALANCE as i128
+  }
+
+  pub fn contract_balance(&self) -> u128 {
+    self.balance(self.contract.as_str())
+  }
+
+  pub fn execute(
+    &mut self,
+    sender: &str,
+    msg: ExecuteMsg,
+  ) -> AnyResult<AppResponse> {
+    self.execute_with_funds(sender, msg, &[])
+  }
+
+  pub fn execute_with_funds(
+    &mut self,
+    sender: &str,
+    msg: ExecuteMsg,
+    funds: &[Coin],
+  ) -> AnyResult<AppResponse> {
+    self.app.execute_contract(
+      Addr::unchecked(sender),
+      self.contract.clone(),
+      &msg,
+      funds,
+    )
+  }
+
+  // Transfer to the contract with the message, native coins are attached,
+  // cw20 tokens are sent with the hook
+  pub fn pay(
+    &mut self,
+    sender: &str,
+    amount: u128,
+    msg: ExecuteMsg,
+    hook: ReceiveMsg,
+  ) -> AnyResult<AppResponse> {
+    match self.asset {
+      Asset::Native => {
+        self.execute_with_funds(sender, msg, &[Coin::new(amount, DENOM)])
+      }
+
+      Asset::Cw20 => self.send_tokens(sender, amount, hook),
+    }
+  }
+
+  pub fn send_tokens(
+    &mut self,
+    sender: &str,
+    amount: u128,
+    hook: ReceiveMsg,
+  ) -> AnyResult<AppResponse> {
+    let token = self.token.clone().expect("cw20 suite");
+
+    self.app.execute_contract(
+      Addr::unchecked(sender),
+      token,
+      &Cw20ExecuteMsg::Send {
+        contract: self.contract.to_string(),
+        amount: Uint128::new(amount),
+        msg: to_json_binary(&hook).unwrap(),
+      },
+      &[],
+    )
+  }
+
+  pub fn advance(&mut self, seconds: u64) {
+    self.app.update_block(|block| {
+      block.time = block.time.plus_seconds(seconds);
+      block.height += 1;
+    });
+  }
+
+  pub fn query<T: serde::de::DeserializeOwned>(&self, msg: &QueryMsg) -> T {
+    self
+      .app
+      .wrap()
+      .query_wasm_smart(&self.contract, msg)
+      .unwrap()
+  }
+
+  pub fn order(&self, order_id: u64) -> OrderResponse {
+    self.query(&QueryMsg::Order { order_id })
+  }
+
+  pub fn reputation(&self, address: &str) -> Reputation {
+    self.query(&QueryMsg::Reputation {
+      address: Addr::unchecked(address),
+    })
+  }
+
+  pub fn stake(
+    &mut self,
+    courier: &str,
+    amount: u128,
+  ) -> AnyResult<AppResponse> {
+    self.pay(
+      courier,
+      amount,
+      ExecuteMsg::Stake {
+        amount: Uint128::new(amount),
+      },
+      ReceiveMsg::Stake {},
+    )
+  }
+
+  pub fn status(&self, order_id: u64) -> Status {
+    self.query(&QueryMsg::Status { order_id })
+  }
+
+  pub fn create_order(
+    &mut self,
+    config: impl FnOnce(&mut CreateOrderMsg),
+  ) -> u64 {
+    let mut msg = create_order_msg();
+    config(&mut msg);
+
+    let response = self.execute(OWNER, ExecuteMsg::CreateOrder(msg)).unwrap();
+    cosmwasm_std::from_json(response.data.unwrap()).unwrap()
+  }
+
+  pub fn pay_for_shipping(
+    &mut self,
+    order_id: u64,
+    amount: u128,
+  ) -> AnyResult<AppResponse> {
+    self.pay(
+      OWNER,
+      amount,
+      ExecuteMsg::MakePayForShipping { order_id },
+      ReceiveMsg::PayForShipping { order_id },
+    )
+  }
+
+  pub fn make_deposit(
+    &mut self,
+    courier: &str,
+    order_id: u64,
+    amount: u128,
+  ) -> AnyResult<AppResponse> {
+    self.pay(
+      courier,
+      amount,
+      ExecuteMsg::MakeDepositForShipping { order_id },
+      ReceiveMsg::DepositForShipping { order_id },
+    )
+  }
+
+  pub fn apply(
+    &mut self,
+    courier: &str,
+    order_id: u64,
+    price: Option<u128>,
+  ) -> AnyResult<AppResponse> {
+    self.execute(
+      courier,
+      ExecuteMsg::AcceptApplication {
+        order_id,
+        price: price.map(Uint128::new),
+        message: Some("on my way".into()),
+        encryption_key: encryption_key(),
+      },
+    )
+  }
+
+  pub fn approve(
+    &mut self,
+    courier: &str,
+    order_id: u64,
+  ) -> AnyResult<AppResponse> {
+    self.execute(
+      OWNER,
+      ExecuteMsg::ApproveCourier {
+        order_id,
+        courier: Addr::unchecked(courier),
+      },
+    )
+  }
+
+  pub fn set_details(&mut self, order_id: u64) -> AnyResult<AppResponse> {
+    self.execute(
+      OWNER,
+      ExecuteMsg::SetDetails {
+        order_id,
+        details: envelope(),
+      },
+    )
+  }
+
+  // The owner gives the parcel and the courier confirms the pickup
+  pub fn hand_off(&mut self, courier: &str, order_id: u64) {
+    self
+      .execute(OWNER, ExecuteMsg::ParcelIssued { order_id })
+      .unwrap();
+    self
+      .execute(courier, ExecuteMsg::PickedUp { order_id })
+      .unwrap();
+  }
+
+  pub fn confirm(
+    &mut self,
+    order_id: u64,
+    parcel: Option<u32>,
+  ) -> AnyResult<AppResponse> {
+    let sign = self.sign_coupon(order_id, parcel);
+    self.execute(
+      COURIER,
+      ExecuteMsg::ConfirmDelivery {
+        order_id,
+        sign,
+        parcel,
+      },
+    )
+  }
+
+  // Signature of the coupon by the recipient, for the current courier
+  pub fn sign_coupon(&self, order_id: u64, parcel: Option<u32>) -> String {
+    let coupon: CouponMessageResponse =
+      self.query(&QueryMsg::CouponMessage { order_id, parcel });
+
+    sign_hash(coupon.message_hash.as_slice())
+  }
+
+  // Create and pay the order, the courier is approved
+  pub fn approved_order(&mut self) -> u64 {
+    let order_id = self.create_order(|_| {});
+    self.pay_for_shipping(order_id, PAYMENT).unwrap();
+    self.apply(COURIER, order_id, None).unwrap();
+    self.approve(COURIER, order_id).unwrap();
+    order_id
+  }
+
+  // The courier made the deposit, the order waits for details
+  pub fn deposited_order(&mut self) -> u64 {
+    let order_id = self.approved_order();
+    self.make_deposit(COURIER, order_id, DEPOSIT).unwrap();
+    order_id
+  }
+
+  // The order waits for the handoff of the parcel
+  pub fn detailed_order(&mut self) -> u64 {
+    let order_id = self.deposited_order();
+    self.set_details(order_id).unwrap();
+    order_id
+  }
+
+  // The parcel is on the way
+  pub fn order_in_progress(&mut self) -> u64 {
+    let order_id = self.detailed_order();
+    self.hand_off(COURIER, order_id);
+    order_id
+  }
+
+  pub fn cancel(&mut self, sender: &str, order_id: u64) -> bool {
+    let response = self
+      .execute(sender, ExecuteMsg::CancelDelivery { order_id })
+      .unwrap();
+
+    cosmwasm_std::from_json(response.data.unwrap()).unwrap()
+  }
+}
+
+pub fn recipient_public_key() -> String {
+  let key = SigningKey::from_bytes(&RECIPIENT_SECRET.into()).unwrap();
+  hex::encode(key.verifying_key().to_encoded_point(true).as_bytes())
+}
+
+pub fn sign_hash(hash: &[u8]) -> String {
+  let key = SigningKey::from_bytes(&RECIPIENT_SECRET.into()).unwrap();
+  let signature: Signature = key.sign_prehash(hash).unwrap();
+  hex::encode(signature.to_bytes())
+}
+
+pub fn ed25519_public_key() -> String {
+  let key = ed25519_zebra::SigningKey::from(RECIPIENT_SECRET);
+  hex::encode(ed25519_zebra::VerificationKey::from(&key))
+}
+
+pub fn ed25519_sign(message: &[u8]) -> String {
+  let key = ed25519_zebra::SigningKey::from(RECIPIENT_SECRET);
+  hex::encode(<[u8; 64]>::from(key.sign(message)))
+}
+
+pub fn area(geohash: &str) -> Area {
+  Area::Geohash(geohash.into())
+}
+
+pub fn create_order_msg() -> CreateOrderMsg {
+  CreateOrderMsg {
+    location: InstantiateLocationInfo {
+      from: area("u4pruyd"),
+      to: area("u4pruyf"),
+    },
+    confirm_public_key: ConfirmPublicKey::Secp256k1(recipient_public_key()),
+    deposit_amount: Uint128::new(DEPOSIT),
+    payment_amount: Uint128::new(PAYMENT),
+    expiration_times: None,
+    courier_requirement: None,
+    next_legs: None,
+    parcels: None,
+    declared_value: None,
+    bonded: None,
+  }
+}
+
+pub fn encryption_key() -> EncryptionKey {
+  EncryptionKey {
+    algorithm: EncryptionAlgorithm::X25519XSalsa20Poly1305,
+    public_key: Binary::from(vec![1; 32]),
+  }
+}
+
+pub fn envelope() -> Envelope {
+  Envelope {
+    algorithm: EncryptionAlgorithm::X25519XSalsa20Poly1305,
+    ephemeral_public_key: Binary::from(vec![2; 32]),
+    nonce: Binary::from(vec![3; 24]),
+    ciphertext: Binary::from(b"encrypted location".to_vec()),
+  }
+}
+
+pub fn assert_error(result: AnyResult<AppResponse>, expected: ContractError) {
+  let error = result.expect_err("the message must fail");
+  assert_eq!(error.root_cause().to_string(), expected.to_string());
+}
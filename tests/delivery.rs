@@ -0,0 +1,1277 @@
+mod common;
+
+use cosmwasm_std::testing::{mock_dependencies, mock_info, mock_env};
+use cosmwasm_std::{to_json_binary, Uint128, Binary, Coin, Addr};
+use figaro_contract::contract::instantiate;
+use cw20::Cw20ReceiveMsg;
+use cw_asset::AssetInfo;
+use figaro_contract::*;
+
+use common::*;
+
+#[test]
+fn delivery_flow() {
+  for asset in ASSETS {
+    let mut suite = Suite::new(asset);
+    let order_id = suite.create_order(|_| {});
+
+    assert_eq!(suite.status(order_id), Status::WaitPaymentBySender);
+
+    suite.pay_for_shipping(order_id, PAYMENT).unwrap();
+    assert_eq!(suite.status(order_id), Status::WaitForCourier);
+    assert_eq!(suite.contract_balance(), PAYMENT);
+
+    assert_error(
+      suite.apply(OWNER, order_id, None),
+      ContractError::OwnerCannotBeACourier {},
+    );
+
+    suite.apply(COURIER, order_id, None).unwrap();
+    suite.apply(OTHER_COURIER, order_id, None).unwrap();
+
+    let applications: ApplicationsResponse =
+      suite.query(&QueryMsg::Applications {
+        order_id,
+        start_after: None,
+        limit: None,
+      });
+    assert_eq!(applications.applications.len(), 2);
+    assert_eq!(suite.status(order_id), Status::WaitForCourier);
+
+    suite.approve(COURIER, order_id).unwrap();
+    assert_eq!(suite.status(order_id), Status::WaitDepositByCourier);
+
+    let courier: Option<Addr> = suite.query(&QueryMsg::Courier { order_id });
+    assert_eq!(courier, Some(Addr::unchecked(COURIER)));
+
+    suite.make_deposit(COURIER, order_id, DEPOSIT).unwrap();
+    assert_eq!(suite.status(order_id), Status::WaitSenderDetails);
+    assert_eq!(suite.contract_balance(), PAYMENT + DEPOSIT);
+
+    suite.set_details(order_id).unwrap();
+
+    let order = suite.order(order_id);
+    assert_eq!(order.status, Status::WaitCourierInDepartment);
+    assert_eq!(order.locations.rough.from, area("u4pruyd"));
+    assert_eq!(order.locations.details, Some(envelope()));
+    assert_eq!(order.courier_encryption_key, Some(encryption_key()));
+
+    // the delivery starts only when both sides confirmed the handoff
+    suite
+      .execute(OWNER, ExecuteMsg::ParcelIssued { order_id })
+      .unwrap();
+    assert_eq!(suite.status(order_id), Status::WaitCourierInDepartment);
+
+    assert_error(
+      suite.execute(OWNER, ExecuteMsg::PickedUp { order_id }),
+      ContractError::CourierExpected {},
+    );
+    suite
+      .execute(COURIER, ExecuteMsg::PickedUp { order_id })
+      .unwrap();
+    assert_eq!(suite.status(order_id), Status::InProgress);
+
+    suite.confirm(order_id, None).unwrap();
+    assert_eq!(suite.status(order_id), Status::Delivered);
+
+    assert_eq!(suite.balance_change(OWNER), -(PAYMENT as i128));
+    assert_eq!(suite.balance_change(COURIER), PAYMENT as i128);
+    assert_eq!(suite.contract_balance(), 0);
+
+    let reputation = suite.reputation(COURIER);
+    assert_eq!(reputation.as_courier.completed, 1);
+    assert_eq!(reputation.as_courier.volume, Uint128::new(PAYMENT));
+    assert_eq!(suite.reputation(OWNER).as_sender.completed, 1);
+  }
+}
+
+#[test]
+fn order_snapshot() {
+  for asset in ASSETS {
+    let mut suite = Suite::new(asset);
+    let order_id = suite.create_order(|_| {});
+
+    let order = suite.order(order_id);
+    assert_eq!(order.id, order_id);
+    assert_eq!(order.owner, Addr::unchecked(OWNER));
+    assert_eq!(order.courier, None);
+    assert_eq!(order.status, Status::WaitPaymentBySender);
+    assert_eq!(order.payment_amount, Uint128::new(PAYMENT));
+    assert_eq!(order.deposit_amount, Uint128::new(DEPOSIT));
+    assert_eq!(order.locations.details, None);
+    assert_eq!(order.deadline.expires_at, None);
+
+    let asset: AssetInfo = suite.query(&QueryMsg::PaymentAsset {});
+    assert_eq!(order.asset, asset);
+
+    suite.pay_for_shipping(order_id, PAYMENT).unwrap();
+    suite.apply(COURIER, order_id, None).unwrap();
+    suite.approve(COURIER, order_id).unwrap();
+
+    let order = suite.order(order_id);
+    assert_eq!(order.courier, Some(Addr::unchecked(COURIER)));
+    assert_eq!(order.status, Status::WaitDepositByCourier);
+    assert_eq!(order.nonce, 0);
+    assert!(order.deadline.expires_at.is_some());
+    assert!(order.deadline.courier_can_cancel.is_some());
+  }
+}
+
+#[test]
+fn details_are_encrypted_to_courier_key() {
+  let mut suite = Suite::new(Asset::Native);
+  let order_id = suite.create_order(|_| {});
+  suite.pay_for_shipping(order_id, PAYMENT).unwrap();
+
+  let short_key = EncryptionKey {
+    public_key: Binary::from(vec![1; 31]),
+    ..encryption_key()
+  };
+  let result = suite.execute(
+    COURIER,
+    ExecuteMsg::AcceptApplication {
+      order_id,
+      price: None,
+      message: None,
+      encryption_key: short_key,
+    },
+  );
+  assert_error(
+    result,
+    ContractError::InvalidEncryption(
+      "public key must be 32 bytes, got 31".into(),
+    ),
+  );
+
+  suite.apply(COURIER, order_id, None).unwrap();
+  suite.approve(COURIER, order_id).unwrap();
+  suite.make_deposit(COURIER, order_id, DEPOSIT).unwrap();
+
+  let invalid = [
+    Envelope {
+      algorithm: EncryptionAlgorithm::X25519ChaCha20Poly1305,
+      nonce: Binary::from(vec![3; 12]),
+      ..envelope()
+    },
+    Envelope {
+      nonce: Binary::from(vec![3; 12]),
+      ..envelope()
+    },
+    Envelope {
+      ciphertext: Binary::default(),
+      ..envelope()
+    },
+  ];
+
+  for details in invalid {
+    let result =
+      suite.execute(OWNER, ExecuteMsg::SetDetails { order_id, details });
+    let error = result.unwrap_err().root_cause().to_string();
+    assert!(error.starts_with("Invalid encryption"), "{}", error);
+  }
+
+  suite.set_details(order_id).unwrap();
+}
+
+#[test]
+fn invalid_coupon_is_rejected() {
+  for asset in ASSETS {
+    let mut suite = Suite::new(asset);
+    let order_id = suite.order_in_progress();
+
+    // the coupon of the other order
+    let other_id = suite.order_in_progress();
+    let sign = suite.sign_coupon(other_id, None);
+
+    assert_error(
+      suite.execute(
+        COURIER,
+        ExecuteMsg::ConfirmDelivery {
+          order_id,
+          sign,
+          parcel: None,
+        },
+      ),
+      ContractError::InvalidSignature,
+    );
+    assert_eq!(suite.status(order_id), Status::InProgress);
+  }
+}
+
+#[test]
+fn coupon_of_previous_round_is_rejected() {
+  let mut suite = Suite::new(Asset::Native);
+  let order_id = suite.detailed_order();
+
+  let coupon: CouponMessageResponse = suite.query(&QueryMsg::CouponMessage {
+    order_id,
+    parcel: None,
+  });
+  assert_eq!(coupon.payload.courier, Addr::unchecked(COURIER));
+  assert_eq!(coupon.payload.nonce, 0);
+
+  let leaked_sign = suite.sign_coupon(order_id, None);
+
+  // the order starts over and the same courier is approved again
+  assert!(suite.cancel(COURIER, order_id));
+
+  suite.apply(COURIER, order_id, None).unwrap();
+  suite.approve(COURIER, order_id).unwrap();
+  suite.make_deposit(COURIER, order_id, DEPOSIT).unwrap();
+  suite.set_details(order_id).unwrap();
+  suite.hand_off(COURIER, order_id);
+
+  let result = suite.execute(
+    COURIER,
+    ExecuteMsg::ConfirmDelivery {
+      order_id,
+      sign: leaked_sign,
+      parcel: None,
+    },
+  );
+
+  assert_error(result, ContractError::InvalidSignature);
+
+  let coupon: CouponMessageResponse = suite.query(&QueryMsg::CouponMessage {
+    order_id,
+    parcel: None,
+  });
+  assert_eq!(coupon.payload.nonce, 1);
+
+  suite.confirm(order_id, None).unwrap();
+  assert_eq!(suite.status(order_id), Status::Delivered);
+}
+
+#[test]
+fn coupon_of_previous_courier_is_rejected() {
+  let mut suite = Suite::new(Asset::Native);
+  let order_id = suite.detailed_order();
+  let leaked_sign = suite.sign_coupon(order_id, None);
+
+  assert!(suite.cancel(COURIER, order_id));
+
+  suite.apply(OTHER_COURIER, order_id, None).unwrap();
+  suite.approve(OTHER_COURIER, order_id).unwrap();
+  suite
+    .make_deposit(OTHER_COURIER, order_id, DEPOSIT)
+    .unwrap();
+  suite.set_details(order_id).unwrap();
+  suite.hand_off(OTHER_COURIER, order_id);
+
+  let result = suite.execute(
+    OTHER_COURIER,
+    ExecuteMsg::ConfirmDelivery {
+      order_id,
+      sign: leaked_sign,
+      parcel: None,
+    },
+  );
+
+  assert_error(result, ContractError::InvalidSignature);
+  assert_eq!(suite.status(order_id), Status::InProgress);
+}
+
+#[test]
+fn ed25519_coupon_confirms_delivery() {
+  for asset in ASSETS {
+    let mut suite = Suite::new(asset);
+
+    let order_id = suite.create_order(|msg| {
+      msg.confirm_public_key = ConfirmPublicKey::Ed25519(ed25519_public_key());
+    });
+
+    suite.pay_for_shipping(order_id, PAYMENT).unwrap();
+    suite.apply(COURIER, order_id, None).unwrap();
+    suite.approve(COURIER, order_id).unwrap();
+    suite.make_deposit(COURIER, order_id, DEPOSIT).unwrap();
+    suite.set_details(order_id).unwrap();
+    suite.hand_off(COURIER, order_id);
+
+    // ed25519 keys sign the message itself, not its hash
+    let confirm = |sign| ExecuteMsg::ConfirmDelivery {
+      order_id,
+      sign,
+      parcel: None,
+    };
+    let coupon: CouponMessageResponse = suite.query(&QueryMsg::CouponMessage {
+      order_id,
+      parcel: None,
+    });
+
+    assert_error(
+      suite.execute(COURIER, confirm(ed25519_sign(&coupon.message_hash))),
+      ContractError::InvalidSignature,
+    );
+    assert_error(
+      suite.execute(COURIER, confirm(suite.sign_coupon(order_id, None))),
+      ContractError::InvalidSignature,
+    );
+
+    suite
+      .execute(COURIER, confirm(ed25519_sign(&coupon.message)))
+      .unwrap();
+
+    assert_eq!(suite.status(order_id), Status::Delivered);
+    assert_eq!(suite.balance_change(COURIER), PAYMENT as i128);
+  }
+}
+
+#[test]
+fn confirmation_key_is_validated() {
+  let mut suite = Suite::new(Asset::Native);
+
+  let cases = [
+    (
+      ConfirmPublicKey::Secp256k1(format!("05{}", "11".repeat(32))),
+      "unexpected secp256k1 key prefix 0x05",
+    ),
+    (
+      ConfirmPublicKey::Secp256k1(format!("02{}", "11".repeat(31))),
+      "secp256k1 key with prefix 0x02 must not be 32 bytes",
+    ),
+    (
+      ConfirmPublicKey::Secp256k1(format!("04{}", "11".repeat(32))),
+      "secp256k1 key with prefix 0x04 must not be 33 bytes",
+    ),
+    (
+      ConfirmPublicKey::Secp256k1(format!("02{}", "ff".repeat(32))),
+      "secp256k1 key is not on the curve",
+    ),
+    (
+      ConfirmPublicKey::Secp256k1("recipient".into()),
+      "secp256k1 key is not a hex string",
+    ),
+    (
+      ConfirmPublicKey::Ed25519("11".repeat(33)),
+      "ed25519 key must be 32 bytes, got 33",
+    ),
+  ];
+
+  for (confirm_public_key, reason) in cases {
+    let mut msg = create_order_msg();
+    msg.confirm_public_key = confirm_public_key;
+
+    assert_error(
+      suite.execute(OWNER, ExecuteMsg::CreateOrder(msg)),
+      ContractError::InvalidPublicKeyFormat(reason.into()),
+    );
+  }
+
+  // keys are accepted in upper case hex as well
+  suite.create_order(|msg| {
+    let key = recipient_public_key().to_uppercase();
+    msg.confirm_public_key = ConfirmPublicKey::Secp256k1(key);
+  });
+}
+
+#[test]
+fn order_areas_are_checked() {
+  let mut suite = Suite::new(Asset::Native);
+
+  let point = |lat: &str, lng: &str| Coordinates {
+    lat: lat.parse().unwrap(),
+    lng: lng.parse().unwrap(),
+  };
+  let bounding_box = |south_west, north_east| Area::BoundingBox {
+    south_west,
+    north_east,
+  };
+
+  let invalid = [
+    bounding_box(point("-91", "10"), point("60", "11")),
+    bounding_box(point("59", "10"), point("60", "180.5")),
+    // corners are swapped
+    bounding_box(point("60", "11"), point("59", "10")),
+    area(""),
+    area("u4pruydqqvj8pr"),
+    // `a`, `i`, `l` and `o` are not in the geohash alphabet
+    area("u4pa"),
+  ];
+
+  for area in invalid {
+    let mut msg = create_order_msg();
+    msg.location.to = area;
+
+    let result = suite.execute(OWNER, ExecuteMsg::CreateOrder(msg));
+    let error = result.unwrap_err().root_cause().to_string();
+    assert!(error.starts_with("Invalid area"), "{}", error);
+  }
+
+  let order_id = suite.create_order(|msg| {
+    msg.location.from = area("U4PRUYD");
+    msg.location.to = bounding_box(point("59.9", "10.7"), point("60", "10.8"));
+  });
+
+  let locations = suite.order(order_id).locations;
+  assert_eq!(locations.rough.from, area("u4pruyd"));
+}
+
+#[test]
+fn orders_are_kept_apart() {
+  let mut suite = Suite::new(Asset::Native);
+
+  let first = suite.create_order(|_| {});
+  let second =
+    suite.create_order(|msg| msg.payment_amount = Uint128::new(PAYMENT * 2));
+
+  assert_eq!((first, second), (1, 2));
+
+  suite.pay_for_shipping(second, PAYMENT * 2).unwrap();
+
+  assert_eq!(suite.status(first), Status::WaitPaymentBySender);
+  assert_eq!(suite.status(second), Status::WaitForCourier);
+  assert_eq!(suite.contract_balance(), PAYMENT * 2);
+
+  let funds: FundsInfoResponse =
+    suite.query(&QueryMsg::Funds { order_id: second });
+  assert_eq!(funds.payment, Uint128::new(PAYMENT * 2));
+
+  let waiting: OrdersResponse = suite.query(&QueryMsg::Orders {
+    status: Some(Status::WaitForCourier),
+    start_after: None,
+    limit: None,
+  });
+  assert_eq!(waiting.orders, vec![second]);
+
+  let by_owner: OrdersResponse = suite.query(&QueryMsg::OrdersByOwner {
+    owner: Addr::unchecked(OWNER),
+    start_after: Some(first),
+    limit: None,
+  });
+  assert_eq!(by_owner.orders, vec![second]);
+
+  assert_error(
+    suite.pay_for_shipping(3, PAYMENT),
+    ContractError::OrderNotFound(3),
+  );
+}
+
+#[test]
+fn fee_goes_to_collector() {
+  for asset in ASSETS {
+    let mut suite = Suite::with_config(asset, |msg| {
+      msg.fee = Some(FeeConfig {
+        bps: 1_000,
+        collector: Addr::unchecked(COLLECTOR),
+        on_cancel: FeeOnCancel::Refund,
+      });
+    });
+
+    let order_id = suite.order_in_progress();
+    suite.confirm(order_id, None).unwrap();
+
+    assert_eq!(suite.balance(COLLECTOR), 10);
+    assert_eq!(suite.balance_change(COURIER), PAYMENT as i128 - 10);
+    assert_eq!(suite.contract_balance(), 0);
+  }
+}
+
+#[test]
+fn fee_over_full_payment_is_rejected() {
+  let mut deps = mock_dependencies();
+
+  let msg = InstantiateMsg {
+    token_address: None,
+    native_denom: Some(DENOM.to_string()),
+    arbiter: None,
+    settle_bounty: None,
+    fee: Some(FeeConfig {
+      bps: 10_001,
+      collector: Addr::unchecked(COLLECTOR),
+      on_cancel: FeeOnCancel::Refund,
+    }),
+    insurance_premium_bps: None,
+  };
+
+  let error =
+    instantiate(deps.as_mut(), mock_env(), mock_info(OWNER, &[]), msg)
+      .unwrap_err();
+  assert_eq!(error, ContractError::InvalidFee {});
+}
+
+#[test]
+fn batch_parcels_are_paid_one_by_one() {
+  for asset in ASSETS {
+    let mut suite = Suite::new(asset);
+
+    // the first parcel gets the rest of the payment
+    let order_id = suite.create_order(|msg| {
+      msg.parcels = Some(vec![Parcel {
+        to: area("u4pruyg"),
+        confirm_public_key: msg.confirm_public_key.clone(),
+        payment_amount: Uint128::new(40),
+      }]);
+    });
+
+    suite.pay_for_shipping(order_id, PAYMENT).unwrap();
+    suite.apply(COURIER, order_id, None).unwrap();
+    suite.approve(COURIER, order_id).unwrap();
+    suite.make_deposit(COURIER, order_id, DEPOSIT).unwrap();
+    suite.set_details(order_id).unwrap();
+    suite.hand_off(COURIER, order_id);
+
+    assert_error(
+      suite.execute(
+        COURIER,
+        ExecuteMsg::ConfirmDelivery {
+          order_id,
+          sign: suite.sign_coupon(order_id, None),
+          parcel: Some(2),
+        },
+      ),
+      ContractError::ParcelNotFound(2),
+    );
+
+    // the coupon of one parcel does not confirm the other one
+    assert_error(
+      suite.execute(
+        COURIER,
+        ExecuteMsg::ConfirmDelivery {
+          order_id,
+          sign: suite.sign_coupon(order_id, Some(0)),
+          parcel: Some(1),
+        },
+      ),
+      ContractError::InvalidSignature,
+    );
+
+    suite.confirm(order_id, Some(1)).unwrap();
+
+    // the share of the parcel and the same part of the deposit
+    let order = suite.order(order_id);
+    assert_eq!(order.status, Status::InProgress);
+    assert_eq!(order.delivered_parcels, vec![1]);
+    assert_eq!(order.paid_payment, Uint128::new(40));
+    assert_eq!(order.paid_deposit, Uint128::new(20));
+    assert_eq!(suite.balance_change(COURIER), 40 + 20 - DEPOSIT as i128);
+
+    assert_error(
+      suite.confirm(order_id, Some(1)),
+      ContractError::ParcelAlreadyDelivered(1),
+    );
+
+    suite.confirm(order_id, Some(0)).unwrap();
+
+    assert_eq!(suite.status(order_id), Status::Delivered);
+    assert_eq!(suite.balance_change(COURIER), PAYMENT as i128);
+    assert_eq!(suite.contract_balance(), 0);
+  }
+}
+
+#[test]
+fn batch_parcel_with_zero_share() {
+  for asset in ASSETS {
+    let mut suite = Suite::new(asset);
+
+    // the first parcel gets the rest of the payment, which is zero
+    let order_id = suite.create_order(|msg| {
+      msg.parcels = Some(vec![Parcel {
+        to: area("u4pruyg"),
+        confirm_public_key: msg.confirm_public_key.clone(),
+        payment_amount: Uint128::new(PAYMENT),
+      }]);
+    });
+
+    suite.pay_for_shipping(order_id, PAYMENT).unwrap();
+    suite.apply(COURIER, order_id, None).unwrap();
+    suite.approve(COURIER, order_id).unwrap();
+    suite.make_deposit(COURIER, order_id, DEPOSIT).unwrap();
+    suite.set_details(order_id).unwrap();
+    suite.hand_off(COURIER, order_id);
+
+    suite.confirm(order_id, Some(0)).unwrap();
+
+    let order = suite.order(order_id);
+    assert_eq!(order.status, Status::InProgress);
+    assert_eq!(order.delivered_parcels, vec![0]);
+    assert_eq!(order.paid_payment, Uint128::zero());
+    assert_eq!(suite.balance_change(COURIER), -(DEPOSIT as i128));
+
+    suite.confirm(order_id, Some(1)).unwrap();
+
+    assert_eq!(suite.status(order_id), Status::Delivered);
+    assert_eq!(suite.balance_change(COURIER), PAYMENT as i128);
+    assert_eq!(suite.contract_balance(), 0);
+  }
+}
+
+#[test]
+fn batch_shares_must_fit_payment() {
+  let mut suite = Suite::new(Asset::Native);
+
+  let mut msg = create_order_msg();
+  msg.parcels = Some(vec![Parcel {
+    to: area("u4pruyg"),
+    confirm_public_key: msg.confirm_public_key.clone(),
+    payment_amount: Uint128::new(PAYMENT + 1),
+  }]);
+
+  let error = suite
+    .execute(OWNER, ExecuteMsg::CreateOrder(msg))
+    .unwrap_err()
+    .root_cause()
+    .to_string();
+  assert!(error.starts_with("Invalid parcels"), "{}", error);
+
+  let order_id = suite.create_order(|msg| {
+    msg.parcels = Some(vec![Parcel {
+      to: area("u4pruyg"),
+      confirm_public_key: msg.confirm_public_key.clone(),
+      payment_amount: Uint128::new(40),
+    }]);
+  });
+  suite.pay_for_shipping(order_id, PAYMENT).unwrap();
+
+  // the price of the courier must cover the additional parcels
+  let error = suite
+    .apply(COURIER, order_id, Some(30))
+    .unwrap_err()
+    .root_cause()
+    .to_string();
+  assert!(error.starts_with("Invalid parcels"), "{}", error);
+}
+
+#[test]
+fn relay_legs_are_paid_one_by_one() {
+  for asset in ASSETS {
+    let mut suite = Suite::new(asset);
+
+    let order_id = suite.create_order(|msg| {
+      msg.next_legs = Some(vec![Leg {
+        to: area("u4pruyh"),
+        confirm_public_key: msg.confirm_public_key.clone(),
+        deposit_amount: Uint128::new(DEPOSIT),
+        payment_amount: Uint128::new(60),
+      }]);
+    });
+
+    // the payment of all legs is made at once
+    assert!(suite.pay_for_shipping(order_id, PAYMENT).is_err());
+    suite.pay_for_shipping(order_id, PAYMENT + 60).unwrap();
+
+    suite.apply(COURIER, order_id, None).unwrap();
+    suite.approve(COURIER, order_id).unwrap();
+    suite.make_deposit(COURIER, order_id, DEPOSIT).unwrap();
+    suite.set_details(order_id).unwrap();
+    suite.hand_off(COURIER, order_id);
+
+    let first_coupon = suite.sign_coupon(order_id, None);
+    suite.confirm(order_id, None).unwrap();
+
+    let order = suite.order(order_id);
+    assert_eq!(order.status, Status::WaitForCourier);
+    assert_eq!(order.leg, 1);
+    assert_eq!(order.courier, None);
+    assert_eq!(order.locations.rough.from, area("u4pruyf"));
+    assert_eq!(order.locations.rough.to, area("u4pruyh"));
+    assert_eq!(order.payment_amount, Uint128::new(60));
+    assert_eq!(suite.balance_change(COURIER), PAYMENT as i128);
+    assert_eq!(suite.contract_balance(), 60);
+
+    suite.apply(OTHER_COURIER, order_id, None).unwrap();
+    suite.approve(OTHER_COURIER, order_id).unwrap();
+    suite
+      .make_deposit(OTHER_COURIER, order_id, DEPOSIT)
+      .unwrap();
+    suite.set_details(order_id).unwrap();
+    suite.hand_off(OTHER_COURIER, order_id);
+
+    // the handoff coupon of the first leg cannot confirm the second one
+    assert_error(
+      suite.execute(
+        OTHER_COURIER,
+        ExecuteMsg::ConfirmDelivery {
+          order_id,
+          sign: first_coupon,
+          parcel: None,
+        },
+      ),
+      ContractError::InvalidSignature,
+    );
+
+    let sign = suite.sign_coupon(order_id, None);
+    suite
+      .execute(
+        OTHER_COURIER,
+        ExecuteMsg::ConfirmDelivery {
+          order_id,
+          sign,
+          parcel: None,
+        },
+      )
+      .unwrap();
+
+    assert_eq!(suite.status(order_id), Status::Delivered);
+    assert_eq!(suite.balance_change(OTHER_COURIER), 60);
+    assert_eq!(suite.balance_change(OWNER), -(PAYMENT as i128) - 60);
+    assert_eq!(suite.contract_balance(), 0);
+  }
+}
+
+#[test]
+fn relay_legs_are_limited() {
+  let mut suite = Suite::new(Asset::Native);
+
+  let mut msg = create_order_msg();
+  let leg = Leg {
+    to: area("u4pruyh"),
+    confirm_public_key: msg.confirm_public_key.clone(),
+    deposit_amount: Uint128::new(DEPOSIT),
+    payment_amount: Uint128::new(PAYMENT),
+  };
+  msg.next_legs = Some(vec![leg; 9]);
+
+  assert_error(
+    suite.execute(OWNER, ExecuteMsg::CreateOrder(msg)),
+    ContractError::TooManyLegs(8),
+  );
+}
+
+#[test]
+fn approved_price_returns_difference() {
+  for asset in ASSETS {
+    let mut suite = Suite::new(asset);
+    let order_id = suite.create_order(|_| {});
+    suite.pay_for_shipping(order_id, PAYMENT).unwrap();
+
+    assert_error(
+      suite.apply(COURIER, order_id, Some(PAYMENT + 1)),
+      ContractError::PriceExceedsPayment {},
+    );
+
+    suite.apply(COURIER, order_id, Some(80)).unwrap();
+    suite.approve(COURIER, order_id).unwrap();
+
+    let funds: FundsInfoResponse = suite.query(&QueryMsg::Funds { order_id });
+    assert_eq!(funds.payment, Uint128::new(80));
+    assert_eq!(suite.balance_change(OWNER), -80);
+    assert_eq!(suite.contract_balance(), 80);
+  }
+}
+
+#[test]
+fn applications_are_cleared_when_courier_leaves() {
+  let mut suite = Suite::new(Asset::Native);
+  let order_id = suite.create_order(|_| {});
+  suite.pay_for_shipping(order_id, PAYMENT).unwrap();
+
+  suite.apply(COURIER, order_id, Some(50)).unwrap();
+  suite.apply(OTHER_COURIER, order_id, Some(80)).unwrap();
+  suite.approve(COURIER, order_id).unwrap();
+
+  // the payment is reduced to the price of the approved courier
+  assert!(suite.cancel(COURIER, order_id));
+  assert_eq!(suite.status(order_id), Status::WaitForCourier);
+
+  let applications: ApplicationsResponse =
+    suite.query(&QueryMsg::Applications {
+      order_id,
+      start_after: None,
+      limit: None,
+    });
+  assert!(applications.applications.is_empty());
+
+  assert_error(
+    suite.approve(OTHER_COURIER, order_id),
+    ContractError::ApplicationNotFound {},
+  );
+  assert_error(
+    suite.apply(OTHER_COURIER, order_id, Some(80)),
+    ContractError::PriceExceedsPayment {},
+  );
+
+  suite.apply(OTHER_COURIER, order_id, Some(50)).unwrap();
+  suite.approve(OTHER_COURIER, order_id).unwrap();
+
+  assert_eq!(suite.balance_change(OWNER), -50);
+  assert_eq!(suite.contract_balance(), 50);
+}
+
+#[test]
+fn rejected_application_cannot_be_approved() {
+  let mut suite = Suite::new(Asset::Native);
+  let order_id = suite.create_order(|_| {});
+  suite.pay_for_shipping(order_id, PAYMENT).unwrap();
+
+  suite.apply(COURIER, order_id, None).unwrap();
+  suite.apply(OTHER_COURIER, order_id, None).unwrap();
+
+  let reject = ExecuteMsg::RejectApplication {
+    order_id,
+    courier: Addr::unchecked(OTHER_COURIER),
+  };
+
+  assert_error(
+    suite.execute(COURIER, reject.clone()),
+    ContractError::OwnerExpected {},
+  );
+
+  suite.execute(OWNER, reject.clone()).unwrap();
+
+  assert_error(
+    suite.execute(OWNER, reject),
+    ContractError::ApplicationNotFound {},
+  );
+  assert_error(
+    suite.approve(OTHER_COURIER, order_id),
+    ContractError::ApplicationNotFound {},
+  );
+
+  let applications: ApplicationsResponse =
+    suite.query(&QueryMsg::Applications {
+      order_id,
+      start_after: None,
+      limit: None,
+    });
+  assert_eq!(applications.applications.len(), 1);
+  assert_eq!(applications.applications[0].courier, COURIER);
+}
+
+#[test]
+fn courier_reputation_is_checked() {
+  let mut suite = Suite::new(Asset::Native);
+  let order_id = suite.order_in_progress();
+  suite.confirm(order_id, None).unwrap();
+
+  let requirement = ReputationRequirement {
+    min_completed: 1,
+    max_failed: Some(0),
+  };
+  let order_id = suite.create_order(|msg| {
+    msg.courier_requirement = Some(requirement.clone());
+  });
+  suite.pay_for_shipping(order_id, PAYMENT).unwrap();
+
+  let order = suite.order(order_id);
+  assert_eq!(order.courier_requirement, Some(requirement));
+
+  assert_error(
+    suite.apply(OTHER_COURIER, order_id, None),
+    ContractError::ReputationTooLow {},
+  );
+
+  suite.apply(COURIER, order_id, None).unwrap();
+}
+
+#[test]
+fn update_order_raises_and_lowers_payment() {
+  for asset in ASSETS {
+    let mut suite = Suite::new(asset);
+    let order_id = suite.create_order(|_| {});
+    suite.pay_for_shipping(order_id, PAYMENT).unwrap();
+
+    let update = |payment_amount: u128| {
+      ExecuteMsg::UpdateOrder(UpdateOrderMsg {
+        order_id,
+        location: None,
+        deposit_amount: None,
+        payment_amount: Some(Uint128::new(payment_amount)),
+        expiration_times: None,
+      })
+    };
+
+    // the difference is paid with the message, cw20 with the allowance
+    let funds = match asset {
+      Asset::Native => vec![Coin::new(50, DENOM)],
+      Asset::Cw20 => vec![],
+    };
+
+    suite
+      .execute_with_funds(OWNER, update(150), &funds)
+      .unwrap();
+
+    assert_eq!(suite.order(order_id).payment_amount, Uint128::new(150));
+    assert_eq!(suite.contract_balance(), 150);
+
+    suite.execute(OWNER, update(120)).unwrap();
+
+    assert_eq!(suite.order(order_id).payment_amount, Uint128::new(120));
+    assert_eq!(suite.contract_balance(), 120);
+    assert_eq!(suite.balance_change(OWNER), -120);
+  }
+}
+
+#[test]
+fn update_order_rejects_funds_without_raise() {
+  let mut suite = Suite::new(Asset::Native);
+  let order_id = suite.create_order(|_| {});
+  suite.pay_for_shipping(order_id, PAYMENT).unwrap();
+
+  let result = suite.execute_with_funds(
+    OWNER,
+    ExecuteMsg::UpdateOrder(UpdateOrderMsg {
+      order_id,
+      location: None,
+      deposit_amount: None,
+      payment_amount: Some(Uint128::new(80)),
+      expiration_times: None,
+    }),
+    &[Coin::new(10, DENOM)],
+  );
+
+  assert_error(result, ContractError::UnexpectedFunds {});
+  assert_eq!(suite.order(order_id).payment_amount, Uint128::new(PAYMENT));
+}
+
+#[test]
+fn update_order_after_approval_is_rejected() {
+  let mut suite = Suite::new(Asset::Native);
+  let order_id = suite.approved_order();
+
+  let result = suite.execute(
+    OWNER,
+    ExecuteMsg::UpdateOrder(UpdateOrderMsg {
+      order_id,
+      location: None,
+      deposit_amount: Some(Uint128::new(DEPOSIT * 2)),
+      payment_amount: None,
+      expiration_times: None,
+    }),
+  );
+
+  assert_error(
+    result,
+    ContractError::UnexpectedStatus(
+      Status::WaitDepositByCourier,
+      Status::WaitForCourier,
+    ),
+  );
+}
+
+#[test]
+fn top_up_and_tip() {
+  for asset in ASSETS {
+    let mut suite = Suite::new(asset);
+    let order_id = suite.create_order(|_| {});
+    suite.pay_for_shipping(order_id, PAYMENT).unwrap();
+
+    assert_error(
+      suite.execute(
+        OWNER,
+        ExecuteMsg::TopUpPayment {
+          order_id,
+          amount: Uint128::zero(),
+        },
+      ),
+      ContractError::ZeroAmount {},
+    );
+
+    suite
+      .pay(
+        OWNER,
+        20,
+        ExecuteMsg::TopUpPayment {
+          order_id,
+          amount: Uint128::new(20),
+        },
+        ReceiveMsg::TopUpPayment { order_id },
+      )
+      .unwrap();
+
+    assert_eq!(suite.order(order_id).payment_amount, Uint128::new(120));
+
+    suite.apply(COURIER, order_id, None).unwrap();
+    suite.approve(COURIER, order_id).unwrap();
+    suite.make_deposit(COURIER, order_id, DEPOSIT).unwrap();
+    suite.set_details(order_id).unwrap();
+    suite.hand_off(COURIER, order_id);
+    suite.confirm(order_id, None).unwrap();
+
+    suite
+      .pay(
+        OWNER,
+        5,
+        ExecuteMsg::Tip {
+          order_id,
+          amount: Uint128::new(5),
+        },
+        ReceiveMsg::Tip { order_id },
+      )
+      .unwrap();
+
+    assert_eq!(suite.balance_change(COURIER), 125);
+    assert_eq!(suite.balance_change(OWNER), -125);
+    assert_eq!(suite.contract_balance(), 0);
+  }
+}
+
+#[test]
+fn bonded_order_locks_stake() {
+  for asset in ASSETS {
+    let mut suite = Suite::with_config(asset, |msg| {
+      msg.fee = Some(FeeConfig {
+        bps: 10_000,
+        collector: Addr::unchecked(COLLECTOR),
+        on_cancel: FeeOnCancel::Refund,
+      });
+    });
+    suite.stake(COURIER, DEPOSIT).unwrap();
+
+    let order_id = suite.create_order(|msg| msg.bonded = Some(true));
+    suite.pay_for_shipping(order_id, PAYMENT).unwrap();
+
+    // without the free stake the courier cannot apply
+    assert_error(
+      suite.apply(OTHER_COURIER, order_id, None),
+      ContractError::InsufficientStake(Uint128::zero()),
+    );
+
+    suite.apply(COURIER, order_id, None).unwrap();
+    suite.approve(COURIER, order_id).unwrap();
+    assert_eq!(suite.status(order_id), Status::WaitSenderDetails);
+
+    let stake: Stake = suite.query(&QueryMsg::Stake {
+      address: Addr::unchecked(COURIER),
+    });
+    assert_eq!(stake.locked, Uint128::new(DEPOSIT));
+
+    let unstake = ExecuteMsg::Unstake {
+      amount: Uint128::new(DEPOSIT),
+    };
+    assert_error(
+      suite.execute(COURIER, unstake.clone()),
+      ContractError::InsufficientStake(Uint128::zero()),
+    );
+
+    suite.set_details(order_id).unwrap();
+    suite.hand_off(COURIER, order_id);
+
+    // the whole payment is the fee, nothing is transferred to the courier
+    suite.confirm(order_id, None).unwrap();
+
+    assert_eq!(suite.status(order_id), Status::Delivered);
+    assert_eq!(suite.balance(COLLECTOR), PAYMENT);
+
+    suite.execute(COURIER, unstake).unwrap();
+
+    assert_eq!(suite.balance_change(COURIER), 0);
+    assert_eq!(suite.contract_balance(), 0);
+  }
+}
+
+#[test]
+fn reviews_after_delivery() {
+  let mut suite = Suite::new(Asset::Native);
+  let order_id = suite.order_in_progress();
+
+  let review = |rating| ExecuteMsg::LeaveReview {
+    order_id,
+    rating,
+    review: Some("fast".into()),
+  };
+
+  assert_error(
+    suite.execute(OWNER, review(5)),
+    ContractError::UnexpectedStatus(Status::InProgress, Status::Delivered),
+  );
+
+  suite.confirm(order_id, None).unwrap();
+
+  suite.execute(OWNER, review(5)).unwrap();
+  assert_error(
+    suite.execute(OWNER, review(4)),
+    ContractError::AlreadyReviewed {},
+  );
+  assert_error(
+    suite.execute(COURIER, review(6)),
+    ContractError::InvalidRating(1, 5),
+  );
+  assert_error(
+    suite.execute(KEEPER, review(1)),
+    ContractError::OwnerOrCourierExpected {},
+  );
+
+  let reviews: ReviewsResponse = suite.query(&QueryMsg::Reviews {
+    address: Addr::unchecked(COURIER),
+    start_after: None,
+    limit: None,
+  });
+
+  assert_eq!(reviews.reviews.len(), 1);
+  assert_eq!(reviews.reviews[0].rating, 5);
+  assert_eq!(reviews.reviews[0].author, Addr::unchecked(OWNER));
+
+  let ratings = suite.reputation(COURIER).as_courier.ratings;
+  assert_eq!((ratings.count, ratings.total), (1, 5));
+
+  suite.advance(ExpirationTimes::default().for_review);
+  assert_error(
+    suite.execute(COURIER, review(4)),
+    ContractError::ReviewTimeOver {},
+  );
+}
+
+#[test]
+fn open_orders_by_departure_area() {
+  let mut suite = Suite::new(Asset::Native);
+
+  let cheap = suite.create_order(|msg| msg.payment_amount = Uint128::new(10));
+  let far = suite.create_order(|msg| {
+    msg.location.from = area("ezs42");
+  });
+  let near = suite.create_order(|_| {});
+  // unpaid orders are not open yet
+  suite.create_order(|_| {});
+
+  suite.pay_for_shipping(cheap, 10).unwrap();
+  suite.pay_for_shipping(far, PAYMENT).unwrap();
+  suite.pay_for_shipping(near, PAYMENT).unwrap();
+
+  let open_orders =
+    |area: Area, min_payment: Option<u128>| QueryMsg::OpenOrders {
+      area,
+      min_payment: min_payment.map(Uint128::new),
+      max_deposit: None,
+      start_after: None,
+      limit: None,
+    };
+
+  let response: OpenOrdersResponse =
+    suite.query(&open_orders(area("u4p"), None));
+  assert_eq!(response.orders, vec![cheap, near]);
+
+  let response: OpenOrdersResponse =
+    suite.query(&open_orders(area("u4p"), Some(50)));
+  assert_eq!(response.orders, vec![near]);
+
+  let response: OpenOrdersResponse = suite.query(&open_orders(area("e"), None));
+  assert_eq!(response.orders, vec![far]);
+
+  // the order leaves the index once the courier is approved
+  suite.apply(COURIER, near, None).unwrap();
+  suite.approve(COURIER, near).unwrap();
+
+  let response: OpenOrdersResponse =
+    suite.query(&open_orders(area("u4pruyd"), None));
+  assert_eq!(response.orders, vec![cheap]);
+}
+
+#[test]
+fn open_orders_are_paged_by_scanned_id() {
+  let mut suite = Suite::new(Asset::Native);
+
+  let cheap = suite.create_order(|msg| msg.payment_amount = Uint128::new(10));
+  let far = suite.create_order(|msg| {
+    msg.location.from = area("ezs42");
+  });
+  let near = suite.create_order(|_| {});
+
+  suite.pay_for_shipping(cheap, 10).unwrap();
+  suite.pay_for_shipping(far, PAYMENT).unwrap();
+  suite.pay_for_shipping(near, PAYMENT).unwrap();
+
+  let response: OpenOrdersResponse = suite.query(&QueryMsg::OpenOrders {
+    area: area("u4p"),
+    min_payment: Some(Uint128::new(50)),
+    max_deposit: None,
+    start_after: None,
+    limit: None,
+  });
+
+  assert_eq!(response.orders, vec![near]);
+  assert_eq!(response.last_scanned, Some(near));
+
+  let response: OpenOrdersResponse = suite.query(&QueryMsg::OpenOrders {
+    area: area("u4p"),
+    min_payment: None,
+    max_deposit: None,
+    start_after: response.last_scanned,
+    limit: None,
+  });
+
+  assert_eq!(response.orders, Vec::<u64>::new());
+  assert_eq!(response.last_scanned, None);
+}
+
+#[test]
+fn native_payment_expects_exact_funds() {
+  let mut suite = Suite::new(Asset::Native);
+  let order_id = suite.create_order(|_| {});
+
+  let asset: AssetInfo = suite.query(&QueryMsg::PaymentAsset {});
+  assert_eq!(asset, AssetInfo::native(DENOM));
+
+  assert_error(
+    suite.pay_for_shipping(order_id, PAYMENT - 1),
+    ContractError::InvalidFunds(Coin::new(PAYMENT, DENOM)),
+  );
+  assert_error(
+    suite.execute(OWNER, ExecuteMsg::MakePayForShipping { order_id }),
+    ContractError::InvalidFunds(Coin::new(PAYMENT, DENOM)),
+  );
+
+  assert_eq!(suite.status(order_id), Status::WaitPaymentBySender);
+  assert_eq!(suite.contract_balance(), 0);
+}
+
+#[test]
+fn funds_with_non_payment_message_are_rejected() {
+  let mut suite = Suite::new(Asset::Native);
+
+  let result = suite.execute_with_funds(
+    OWNER,
+    ExecuteMsg::CreateOrder(create_order_msg()),
+    &[Coin::new(10, DENOM)],
+  );
+
+  assert_error(result, ContractError::UnexpectedFunds {});
+  assert_eq!(suite.contract_balance(), 0);
+}
+
+#[test]
+fn cw20_payment_rejects_native_funds() {
+  let mut suite = Suite::new(Asset::Cw20);
+  let order_id = suite.create_order(|_| {});
+
+  let result = suite.execute_with_funds(
+    OWNER,
+    ExecuteMsg::MakePayForShipping { order_id },
+    &[Coin::new(PAYMENT, DENOM)],
+  );
+
+  assert_error(result, ContractError::UnexpectedFunds {});
+  assert_eq!(suite.status(order_id), Status::WaitPaymentBySender);
+}
+
+#[test]
+fn cw20_payment_with_allowance() {
+  let mut suite = Suite::new(Asset::Cw20);
+  let order_id = suite.create_order(|_| {});
+
+  suite
+    .execute(OWNER, ExecuteMsg::MakePayForShipping { order_id })
+    .unwrap();
+
+  assert_eq!(suite.status(order_id), Status::WaitForCourier);
+  assert_eq!(suite.contract_balance(), PAYMENT);
+}
+
+#[test]
+fn receive_hook_checks_token_and_amount() {
+  let mut suite = Suite::new(Asset::Cw20);
+  let order_id = suite.create_order(|_| {});
+
+  assert_error(
+    suite.send_tokens(
+      OWNER,
+      PAYMENT - 1,
+      ReceiveMsg::PayForShipping { order_id },
+    ),
+    ContractError::InvalidAmount(Uint128::new(PAYMENT)),
+  );
+
+  // tokens of the other contract are not accepted
+  let receive = ExecuteMsg::Receive(Cw20ReceiveMsg {
+    sender: OWNER.into(),
+    amount: Uint128::new(PAYMENT),
+    msg: to_json_binary(&ReceiveMsg::PayForShipping { order_id }).unwrap(),
+  });
+
+  assert_error(
+    suite.execute(OWNER, receive),
+    ContractError::UnexpectedToken {},
+  );
+
+  assert_eq!(suite.status(order_id), Status::WaitPaymentBySender);
+  assert_eq!(suite.balance_change(OWNER), 0);
+}
@@ -0,0 +1,235 @@
+mod common;
+
+use cosmwasm_std::testing::{
+  mock_dependencies,
+  mock_info,
+  mock_env,
+  MockQuerier,
+  MockStorage,
+  MockApi,
+};
+use cosmwasm_std::{
+  from_json,
+  OwnedDeps,
+  Uint128,
+  Addr,
+};
+use cw_storage_plus::Item;
+use sha2::{Digest, Sha256};
+use cw2::set_contract_version;
+
+use figaro_contract::contract::{execute, migrate, query};
+use figaro_contract::*;
+
+use common::*;
+
+type Deps = OwnedDeps<MockStorage, MockApi, MockQuerier>;
+
+// Single delivery of the 0.1.x contract with the given status
+fn legacy_contract(status: Status) -> Deps {
+  let mut deps = mock_dependencies();
+  let storage = deps.as_mut().storage;
+
+  set_contract_version(storage, "crates.io:cosm-figaro", "0.1.0").unwrap();
+
+  let strings = [
+    ("confirm_public_key", recipient_public_key()),
+    ("from_rough_location", "u4pruyd".to_string()),
+    ("to_rough_location", "[[59.9,10.7],[60.0,10.8]]".to_string()),
+  ];
+
+  for (key, value) in strings {
+    Item::<String>::new(key).save(storage, &value).unwrap();
+  }
+
+  Item::new("expiration_times")
+    .save(storage, &ExpirationTimes::default())
+    .unwrap();
+  Item::new("fixation_time")
+    .save(storage, &mock_env().block.time)
+    .unwrap();
+  Item::new("available_time")
+    .save(storage, &ExpirationTimes::default().for_wait_delivery)
+    .unwrap();
+  Item::new("deposit_amount")
+    .save(storage, &Uint128::new(DEPOSIT))
+    .unwrap();
+  Item::new("payment_amount")
+    .save(storage, &Uint128::new(PAYMENT))
+    .unwrap();
+  Item::new("courier")
+    .save(storage, &Addr::unchecked(COURIER))
+    .unwrap();
+  Item::new("owner")
+    .save(storage, &Addr::unchecked(OWNER))
+    .unwrap();
+  Item::new("token")
+    .save(storage, &Addr::unchecked("token"))
+    .unwrap();
+  Item::new("status").save(storage, &status).unwrap();
+
+  deps
+}
+
+fn order(deps: &Deps) -> OrderResponse {
+  let response =
+    query(deps.as_ref(), mock_env(), QueryMsg::Order { order_id: 1 }).unwrap();
+
+  from_json(response).unwrap()
+}
+
+#[test]
+fn legacy_order_becomes_first_order() {
+  let mut deps = legacy_contract(Status::InProgress);
+
+  let response = migrate(deps.as_mut(), mock_env(), MigrateMsg {}).unwrap();
+
+  let attribute = |key: &str| {
+    response
+      .attributes
+      .iter()
+      .find(|attribute| attribute.key == key)
+      .map(|attribute| attribute.value.clone())
+  };
+
+  assert_eq!(attribute("applied").as_deref(), Some("0.2.0"));
+  assert_eq!(attribute("legacy_coupon").as_deref(), Some("true"));
+
+  let order = order(&deps);
+  assert_eq!(order.owner, Addr::unchecked(OWNER));
+  assert_eq!(order.courier, Some(Addr::unchecked(COURIER)));
+  assert_eq!(order.status, Status::InProgress);
+  assert_eq!(order.payment_amount, Uint128::new(PAYMENT));
+  assert_eq!(order.locations.rough.from, area("u4pruyd"));
+  assert!(order.legacy_coupon);
+  assert_eq!(
+    order.locations.rough.to,
+    Area::BoundingBox {
+      south_west: Coordinates {
+        lat: "59.9".parse().unwrap(),
+        lng: "10.7".parse().unwrap(),
+      },
+      north_east: Coordinates {
+        lat: "60.0".parse().unwrap(),
+        lng: "10.8".parse().unwrap(),
+      },
+    }
+  );
+}
+
+#[test]
+fn legacy_coupon_confirms_migrated_delivery() {
+  let mut deps = legacy_contract(Status::InProgress);
+  migrate(deps.as_mut(), mock_env(), MigrateMsg {}).unwrap();
+
+  // coupons of 0.1.x sign sha256 of the contract address
+  let env = mock_env();
+  let sign = sign_hash(&Sha256::digest(env.contract.address.as_bytes()));
+
+  let response = execute(
+    deps.as_mut(),
+    env,
+    mock_info(COURIER, &[]),
+    ExecuteMsg::ConfirmDelivery {
+      order_id: 1,
+      sign,
+      parcel: None,
+    },
+  )
+  .unwrap();
+
+  // the payment and the deposit are transferred to the courier
+  assert_eq!(response.messages.len(), 1);
+}
+
+#[test]
+fn courier_of_migrated_order_registers_encryption_key() {
+  let mut deps = legacy_contract(Status::WaitSenderDetails);
+  migrate(deps.as_mut(), mock_env(), MigrateMsg {}).unwrap();
+
+  let set_details = ExecuteMsg::SetDetails {
+    order_id: 1,
+    details: envelope(),
+  };
+
+  let error = execute(
+    deps.as_mut(),
+    mock_env(),
+    mock_info(OWNER, &[]),
+    set_details.clone(),
+  )
+  .unwrap_err();
+  assert_eq!(error, ContractError::EncryptionKeyNotSet {});
+
+  let set_key = ExecuteMsg::SetEncryptionKey {
+    order_id: 1,
+    encryption_key: encryption_key(),
+  };
+
+  let error = execute(
+    deps.as_mut(),
+    mock_env(),
+    mock_info(OWNER, &[]),
+    set_key.clone(),
+  )
+  .unwrap_err();
+  assert_eq!(error, ContractError::CourierExpected {});
+
+  execute(
+    deps.as_mut(),
+    mock_env(),
+    mock_info(COURIER, &[]),
+    set_key.clone(),
+  )
+  .unwrap();
+
+  let error =
+    execute(deps.as_mut(), mock_env(), mock_info(COURIER, &[]), set_key)
+      .unwrap_err();
+  assert_eq!(error, ContractError::EncryptionKeyAlreadySet {});
+
+  execute(
+    deps.as_mut(),
+    mock_env(),
+    mock_info(OWNER, &[]),
+    set_details,
+  )
+  .unwrap();
+
+  assert_eq!(order(&deps).status, Status::WaitCourierInDepartment);
+}
+
+#[test]
+fn migration_refuses_other_contracts_and_downgrades() {
+  let mut deps = mock_dependencies();
+
+  set_contract_version(deps.as_mut().storage, "crates.io:cw20-base", "0.1.0")
+    .unwrap();
+
+  let error = migrate(deps.as_mut(), mock_env(), MigrateMsg {}).unwrap_err();
+  assert_eq!(
+    error,
+    ContractError::CannotMigrate("crates.io:cw20-base".into())
+  );
+
+  set_contract_version(deps.as_mut().storage, "crates.io:cosm-figaro", "9.0.0")
+    .unwrap();
+
+  let error = migrate(deps.as_mut(), mock_env(), MigrateMsg {}).unwrap_err();
+  assert!(matches!(error, ContractError::CannotDowngrade(..)));
+}
+
+#[test]
+fn legacy_deadline_is_kept() {
+  let mut deps = legacy_contract(Status::InProgress);
+  migrate(deps.as_mut(), mock_env(), MigrateMsg {}).unwrap();
+
+  let time = mock_env().block.time;
+  let deadline = order(&deps).deadline;
+
+  assert_eq!(deadline.fixation_time, Some(time));
+  assert_eq!(
+    deadline.expires_at,
+    Some(time.plus_seconds(ExpirationTimes::default().for_wait_delivery))
+  );
+}
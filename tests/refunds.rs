@@ -0,0 +1,568 @@
+mod common;
+
+use figaro_contract::queries::CancelTerms;
+use figaro_contract::state::Dispute;
+use cosmwasm_std::{Addr, Uint128};
+use figaro_contract::*;
+
+use common::*;
+
+// Longer than any stage of the default expiration times
+const EXPIRED: u64 = 8 * 24 * 3600;
+
+#[test]
+fn owner_closes_unpaid_order() {
+  for asset in ASSETS {
+    let mut suite = Suite::new(asset);
+    let order_id = suite.create_order(|_| {});
+
+    assert!(suite.cancel(OWNER, order_id));
+
+    assert_eq!(suite.status(order_id), Status::Closed);
+    assert_eq!(suite.balance_change(OWNER), 0);
+  }
+}
+
+#[test]
+fn owner_closes_order_waiting_for_courier() {
+  for asset in ASSETS {
+    let mut suite = Suite::new(asset);
+    let order_id = suite.create_order(|_| {});
+    suite.pay_for_shipping(order_id, PAYMENT).unwrap();
+
+    assert!(suite.cancel(OWNER, order_id));
+
+    assert_eq!(suite.status(order_id), Status::Closed);
+    assert_eq!(suite.balance_change(OWNER), 0);
+    assert_eq!(suite.contract_balance(), 0);
+  }
+}
+
+#[test]
+fn owner_cancels_expired_deposit_stage() {
+  for asset in ASSETS {
+    let mut suite = Suite::new(asset);
+    let order_id = suite.approved_order();
+
+    assert!(!suite.cancel(OWNER, order_id));
+
+    suite.advance(EXPIRED);
+    assert!(suite.cancel(OWNER, order_id));
+
+    assert_eq!(suite.status(order_id), Status::Failed);
+    assert_eq!(suite.balance_change(OWNER), 0);
+    assert_eq!(suite.contract_balance(), 0);
+
+    // the courier did not make the deposit in time
+    assert_eq!(suite.reputation(COURIER).as_courier.failed, 1);
+  }
+}
+
+#[test]
+fn owner_cancels_expired_details_stage() {
+  for asset in ASSETS {
+    let mut suite = Suite::new(asset);
+    let order_id = suite.deposited_order();
+
+    assert!(!suite.cancel(OWNER, order_id));
+
+    suite.advance(EXPIRED);
+    assert!(suite.cancel(OWNER, order_id));
+
+    assert_eq!(suite.status(order_id), Status::Failed);
+    assert_eq!(suite.balance_change(OWNER), 0);
+    assert_eq!(suite.balance_change(COURIER), 0);
+    assert_eq!(suite.contract_balance(), 0);
+
+    // the owner did not send the details in time
+    let reputation = suite.reputation(OWNER).as_sender;
+    assert_eq!(reputation.failed, 1);
+    assert_eq!(reputation.cancellations.wait_details, 1);
+  }
+}
+
+#[test]
+fn owner_cancels_expired_handoff_stage() {
+  for asset in ASSETS {
+    let mut suite = Suite::new(asset);
+    let order_id = suite.detailed_order();
+
+    assert!(!suite.cancel(OWNER, order_id));
+
+    suite.advance(EXPIRED);
+    assert!(suite.cancel(OWNER, order_id));
+
+    assert_eq!(suite.status(order_id), Status::Failed);
+    assert_eq!(suite.balance_change(OWNER), 0);
+    assert_eq!(suite.balance_change(COURIER), 0);
+    assert_eq!(suite.contract_balance(), 0);
+  }
+}
+
+#[test]
+fn owner_cancels_expired_delivery() {
+  for asset in ASSETS {
+    let mut suite = Suite::new(asset);
+    let order_id = suite.order_in_progress();
+
+    assert!(!suite.cancel(OWNER, order_id));
+
+    suite.advance(EXPIRED);
+    assert!(suite.cancel(OWNER, order_id));
+
+    // the deposit of the courier goes to the owner
+    assert_eq!(suite.status(order_id), Status::Failed);
+    assert_eq!(suite.balance_change(OWNER), DEPOSIT as i128);
+    assert_eq!(suite.balance_change(COURIER), -(DEPOSIT as i128));
+    assert_eq!(suite.contract_balance(), 0);
+  }
+}
+
+#[test]
+fn courier_leaves_before_deposit() {
+  for asset in ASSETS {
+    let mut suite = Suite::new(asset);
+    let order_id = suite.approved_order();
+
+    assert!(suite.cancel(COURIER, order_id));
+
+    let courier: Option<Addr> = suite.query(&QueryMsg::Courier { order_id });
+    assert_eq!(courier, None);
+    assert_eq!(suite.status(order_id), Status::WaitForCourier);
+    assert_eq!(suite.contract_balance(), PAYMENT);
+
+    let reputation = suite.reputation(COURIER).as_courier;
+    assert_eq!(reputation.cancellations.wait_deposit, 1);
+    assert_eq!(reputation.failed, 0);
+  }
+}
+
+#[test]
+fn courier_leaves_expired_details_stage() {
+  for asset in ASSETS {
+    let mut suite = Suite::new(asset);
+    let order_id = suite.deposited_order();
+
+    assert!(!suite.cancel(COURIER, order_id));
+
+    suite.advance(EXPIRED);
+    assert!(suite.cancel(COURIER, order_id));
+
+    assert_eq!(suite.status(order_id), Status::WaitForCourier);
+    assert_eq!(suite.balance_change(COURIER), 0);
+    assert_eq!(suite.contract_balance(), PAYMENT);
+  }
+}
+
+#[test]
+fn courier_leaves_before_handoff() {
+  for asset in ASSETS {
+    let mut suite = Suite::new(asset);
+    let order_id = suite.detailed_order();
+
+    assert!(suite.cancel(COURIER, order_id));
+
+    let order = suite.order(order_id);
+    assert_eq!(order.courier, None);
+    assert_eq!(order.locations.details, None);
+    assert_eq!(order.courier_encryption_key, None);
+    assert_eq!(order.status, Status::WaitForCourier);
+    assert_eq!(suite.balance_change(COURIER), 0);
+    assert_eq!(suite.contract_balance(), PAYMENT);
+  }
+}
+
+#[test]
+fn courier_leaves_before_pickup() {
+  for asset in ASSETS {
+    let mut suite = Suite::new(asset);
+    let order_id = suite.detailed_order();
+
+    suite
+      .execute(OWNER, ExecuteMsg::ParcelIssued { order_id })
+      .unwrap();
+
+    assert!(!suite.cancel(COURIER, order_id));
+
+    let order = suite.order(order_id);
+    assert_eq!(order.status, Status::WaitCourierInDepartment);
+    assert!(order.parcel_issued);
+    assert_eq!(suite.balance_change(COURIER), -(DEPOSIT as i128));
+    assert_eq!(suite.contract_balance(), PAYMENT + DEPOSIT);
+
+    suite
+      .execute(
+        OWNER,
+        ExecuteMsg::OpenDispute {
+          order_id,
+          reason: "courier took the parcel and left".into(),
+        },
+      )
+      .unwrap();
+
+    assert_eq!(suite.status(order_id), Status::Disputed);
+  }
+}
+
+#[test]
+fn courier_cannot_leave_after_pickup() {
+  for asset in ASSETS {
+    let mut suite = Suite::new(asset);
+    let order_id = suite.detailed_order();
+
+    suite
+      .execute(COURIER, ExecuteMsg::PickedUp { order_id })
+      .unwrap();
+
+    assert!(!suite.cancel(COURIER, order_id));
+
+    let order = suite.order(order_id);
+    assert_eq!(order.status, Status::WaitCourierInDepartment);
+    assert!(order.parcel_picked_up);
+    assert_eq!(suite.contract_balance(), PAYMENT + DEPOSIT);
+  }
+}
+
+#[test]
+fn courier_gives_up_expired_delivery() {
+  for asset in ASSETS {
+    let mut suite = Suite::new(asset);
+    let order_id = suite.order_in_progress();
+
+    assert!(!suite.cancel(COURIER, order_id));
+
+    suite.advance(EXPIRED);
+    assert!(suite.cancel(COURIER, order_id));
+
+    assert_eq!(suite.status(order_id), Status::Failed);
+    assert_eq!(suite.balance_change(OWNER), DEPOSIT as i128);
+    assert_eq!(suite.balance_change(COURIER), -(DEPOSIT as i128));
+  }
+}
+
+#[test]
+fn strangers_cannot_cancel() {
+  let mut suite = Suite::new(Asset::Native);
+  let order_id = suite.approved_order();
+
+  assert_error(
+    suite.execute(OTHER_COURIER, ExecuteMsg::CancelDelivery { order_id }),
+    ContractError::OwnerOrCourierExpected {},
+  );
+}
+
+#[test]
+fn cancel_fee_is_charged() {
+  for asset in ASSETS {
+    let mut suite = Suite::with_config(asset, |msg| {
+      msg.fee = Some(FeeConfig {
+        bps: 1_000,
+        collector: Addr::unchecked(COLLECTOR),
+        on_cancel: FeeOnCancel::Charge,
+      });
+    });
+
+    let order_id = suite.create_order(|_| {});
+    suite.pay_for_shipping(order_id, PAYMENT).unwrap();
+
+    assert!(suite.cancel(OWNER, order_id));
+
+    assert_eq!(suite.balance(COLLECTOR), 10);
+    assert_eq!(suite.balance_change(OWNER), -10);
+    assert_eq!(suite.contract_balance(), 0);
+  }
+}
+
+#[test]
+fn anyone_settles_expired_delivery() {
+  for asset in ASSETS {
+    let mut suite = Suite::with_config(asset, |msg| {
+      msg.settle_bounty = Some(Uint128::new(5));
+    });
+
+    let order_id = suite.order_in_progress();
+
+    assert_error(
+      suite.execute(KEEPER, ExecuteMsg::Settle { order_id }),
+      ContractError::NotExpiredYet {},
+    );
+
+    suite.advance(EXPIRED);
+    suite
+      .execute(KEEPER, ExecuteMsg::Settle { order_id })
+      .unwrap();
+
+    assert_eq!(suite.status(order_id), Status::Failed);
+    assert_eq!(suite.balance_change(KEEPER), 5);
+    assert_eq!(suite.balance_change(OWNER), DEPOSIT as i128 - 5);
+    assert_eq!(suite.contract_balance(), 0);
+  }
+}
+
+#[test]
+fn settle_refunds_both_at_handoff_stage() {
+  for asset in ASSETS {
+    let mut suite = Suite::new(asset);
+    let order_id = suite.detailed_order();
+
+    suite.advance(EXPIRED);
+    suite
+      .execute(KEEPER, ExecuteMsg::Settle { order_id })
+      .unwrap();
+
+    assert_eq!(suite.status(order_id), Status::Failed);
+    assert_eq!(suite.balance_change(OWNER), 0);
+    assert_eq!(suite.balance_change(COURIER), 0);
+    assert_eq!(suite.balance_change(KEEPER), 0);
+  }
+}
+
+#[test]
+fn orders_without_expiration_cannot_be_settled() {
+  let mut suite = Suite::new(Asset::Native);
+  let order_id = suite.create_order(|_| {});
+  suite.pay_for_shipping(order_id, PAYMENT).unwrap();
+
+  suite.advance(EXPIRED);
+
+  assert_error(
+    suite.execute(KEEPER, ExecuteMsg::Settle { order_id }),
+    ContractError::NotExpiredYet {},
+  );
+  assert_eq!(suite.status(order_id), Status::WaitForCourier);
+}
+
+#[test]
+fn deadline_shows_what_each_role_can_do() {
+  let mut suite = Suite::new(Asset::Native);
+  let order_id = suite.order_in_progress();
+
+  let deadline: DeadlineResponse =
+    suite.query(&QueryMsg::Deadline { order_id });
+  let started = deadline.fixation_time.unwrap();
+  let available = deadline.available_time.unwrap();
+
+  assert_eq!(deadline.expires_at, Some(started.plus_seconds(available)));
+  assert!(!deadline.is_expired);
+  assert_eq!(deadline.owner_can_cancel, None);
+  assert_eq!(deadline.courier_can_cancel, None);
+  assert_eq!(deadline.can_settle, None);
+
+  suite.advance(available);
+
+  let expired = CancelTerms {
+    refund_receiver: RefundReceiver::Owner,
+    after_refund: AfterRefund::SetFailed,
+  };
+  let deadline: DeadlineResponse =
+    suite.query(&QueryMsg::Deadline { order_id });
+
+  assert!(deadline.is_expired);
+  assert_eq!(deadline.owner_can_cancel, Some(expired.clone()));
+  assert_eq!(deadline.courier_can_cancel, Some(expired.clone()));
+  assert_eq!(deadline.can_settle, Some(expired));
+}
+
+#[test]
+fn deadline_of_order_waiting_for_courier() {
+  let mut suite = Suite::new(Asset::Native);
+  let order_id = suite.create_order(|_| {});
+  suite.pay_for_shipping(order_id, PAYMENT).unwrap();
+
+  let deadline: DeadlineResponse =
+    suite.query(&QueryMsg::Deadline { order_id });
+
+  assert_eq!(deadline.expires_at, None);
+  assert!(!deadline.is_expired);
+  assert_eq!(
+    deadline.owner_can_cancel,
+    Some(CancelTerms {
+      refund_receiver: RefundReceiver::Owner,
+      after_refund: AfterRefund::SetClosed,
+    }),
+  );
+  assert_eq!(deadline.courier_can_cancel, None);
+  assert_eq!(deadline.can_settle, None);
+}
+
+#[test]
+fn insurance_pays_for_lost_parcel() {
+  for asset in ASSETS {
+    let mut suite = Suite::with_config(asset, |msg| {
+      msg.insurance_premium_bps = Some(100);
+    });
+
+    suite
+      .pay(
+        KEEPER,
+        2_000,
+        ExecuteMsg::FundInsurancePool {
+          amount: Uint128::new(2_000),
+        },
+        ReceiveMsg::FundInsurancePool {},
+      )
+      .unwrap();
+
+    let order_id =
+      suite.create_order(|msg| msg.declared_value = Some(Uint128::new(1_000)));
+
+    // the premium is paid together with the payment
+    assert_eq!(suite.order(order_id).insurance_premium, Uint128::new(10));
+    suite.pay_for_shipping(order_id, PAYMENT + 10).unwrap();
+    suite.apply(COURIER, order_id, None).unwrap();
+    suite.approve(COURIER, order_id).unwrap();
+    suite.make_deposit(COURIER, order_id, DEPOSIT).unwrap();
+    suite.set_details(order_id).unwrap();
+    suite.hand_off(COURIER, order_id);
+
+    suite.advance(EXPIRED);
+    assert!(suite.cancel(OWNER, order_id));
+
+    let pool: InsurancePoolResponse = suite.query(&QueryMsg::InsurancePool {});
+    assert_eq!(pool.balance, Uint128::new(1_010));
+    assert_eq!(pool.premium_bps, Some(100));
+
+    // the payment, the deposit of the courier and the declared value
+    assert_eq!(suite.balance_change(OWNER), 1_040);
+    assert_eq!(suite.contract_balance(), 1_010);
+  }
+}
+
+#[test]
+fn insurance_must_be_enabled() {
+  let mut suite = Suite::new(Asset::Native);
+
+  let mut msg = create_order_msg();
+  msg.declared_value = Some(Uint128::new(1_000));
+
+  assert_error(
+    suite.execute(OWNER, ExecuteMsg::CreateOrder(msg)),
+    ContractError::InsuranceDisabled {},
+  );
+}
+
+#[test]
+fn bonded_courier_is_slashed() {
+  for asset in ASSETS {
+    let mut suite = Suite::new(asset);
+    suite.stake(COURIER, DEPOSIT).unwrap();
+
+    let order_id = suite.create_order(|msg| msg.bonded = Some(true));
+    suite.pay_for_shipping(order_id, PAYMENT).unwrap();
+    suite.apply(COURIER, order_id, None).unwrap();
+    suite.approve(COURIER, order_id).unwrap();
+    suite.set_details(order_id).unwrap();
+    suite.hand_off(COURIER, order_id);
+
+    suite.advance(EXPIRED);
+    assert!(suite.cancel(OWNER, order_id));
+
+    let stake: Stake = suite.query(&QueryMsg::Stake {
+      address: Addr::unchecked(COURIER),
+    });
+
+    assert_eq!(stake, Stake::default());
+    assert_eq!(suite.balance_change(OWNER), DEPOSIT as i128);
+    assert_eq!(suite.balance_change(COURIER), -(DEPOSIT as i128));
+    assert_eq!(suite.contract_balance(), 0);
+  }
+}
+
+#[test]
+fn bonded_courier_leaving_keeps_stake() {
+  let mut suite = Suite::new(Asset::Native);
+  suite.stake(COURIER, DEPOSIT).unwrap();
+
+  let order_id = suite.create_order(|msg| msg.bonded = Some(true));
+  suite.pay_for_shipping(order_id, PAYMENT).unwrap();
+  suite.apply(COURIER, order_id, None).unwrap();
+  suite.approve(COURIER, order_id).unwrap();
+  suite.set_details(order_id).unwrap();
+
+  assert!(suite.cancel(COURIER, order_id));
+
+  let stake: Stake = suite.query(&QueryMsg::Stake {
+    address: Addr::unchecked(COURIER),
+  });
+
+  assert_eq!(stake.locked, Uint128::zero());
+  assert_eq!(stake.amount, Uint128::new(DEPOSIT));
+  assert_eq!(suite.status(order_id), Status::WaitForCourier);
+  assert_eq!(suite.contract_balance(), PAYMENT + DEPOSIT);
+}
+
+#[test]
+fn arbiter_splits_disputed_funds() {
+  for asset in ASSETS {
+    let mut suite = Suite::new(asset);
+    let order_id = suite.order_in_progress();
+
+    suite
+      .execute(
+        COURIER,
+        ExecuteMsg::OpenDispute {
+          order_id,
+          reason: "recipient is unreachable".into(),
+        },
+      )
+      .unwrap();
+
+    assert_eq!(suite.status(order_id), Status::Disputed);
+    assert!(!suite.cancel(OWNER, order_id));
+
+    let ruling = |courier_payment: u128| ExecuteMsg::ResolveDispute {
+      order_id,
+      courier_payment: Uint128::new(courier_payment),
+      courier_deposit: Uint128::new(DEPOSIT),
+      reason: "half of the way is done".into(),
+    };
+
+    assert_error(
+      suite.execute(OWNER, ruling(60)),
+      ContractError::ArbiterExpected {},
+    );
+    assert_error(
+      suite.execute(ARBITER, ruling(PAYMENT + 1)),
+      ContractError::InvalidRuling {},
+    );
+
+    suite.execute(ARBITER, ruling(60)).unwrap();
+
+    let dispute: Option<Dispute> = suite.query(&QueryMsg::Dispute { order_id });
+    let dispute = dispute.unwrap();
+    assert_eq!(dispute.opened_by, Addr::unchecked(COURIER));
+    assert_eq!(dispute.ruling.unwrap().owner_amount, Uint128::new(40));
+
+    assert_eq!(suite.status(order_id), Status::Resolved);
+    assert_eq!(suite.balance_change(COURIER), 60);
+    assert_eq!(suite.balance_change(OWNER), -60);
+    assert_eq!(suite.contract_balance(), 0);
+  }
+}
+
+#[test]
+fn dispute_needs_arbiter_and_delivery_in_progress() {
+  let mut suite = Suite::with_config(Asset::Native, |msg| msg.arbiter = None);
+  let order_id = suite.order_in_progress();
+
+  let open_dispute = |order_id| ExecuteMsg::OpenDispute {
+    order_id,
+    reason: "parcel is damaged".into(),
+  };
+
+  assert_error(
+    suite.execute(OWNER, open_dispute(order_id)),
+    ContractError::ArbiterNotSet {},
+  );
+
+  let mut suite = Suite::new(Asset::Native);
+  let order_id = suite.detailed_order();
+
+  assert_error(
+    suite.execute(OWNER, open_dispute(order_id)),
+    ContractError::UnexpectedStatus(
+      Status::WaitCourierInDepartment,
+      Status::InProgress,
+    ),
+  );
+}